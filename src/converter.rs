@@ -1,5 +1,39 @@
+use geo::{Area, Contains, InteriorPoint, LineString, MultiPolygon, Polygon};
 use osmpbfreader::{Node, NodeId};
 
+/// Assembles relation geometry from `(role, way nodes)` members.
+/// Ways are merged into rings, `inner` rings become holes of the smallest outer ring around them
+pub fn build_multipolygon(members: Vec<(String, Vec<Node>)>) -> Option<geo::Geometry> {
+	let (inner, outer): (Vec<_>, Vec<_>) = members.into_iter()
+		.filter(|(_, nodes)| !nodes.is_empty())
+		.partition(|(role, _)| role == "inner");
+	let to_ring = |nodes: Vec<Node>| Some(LineString::from_iter(nodes.iter().map(|n| geo::Coord { x: n.lon(), y: n.lat() })))
+		.filter(|ring| ring.0.len() >= 3);
+
+	let mut polygons = merge_nodes(outer.into_iter().map(|(_, nodes)| nodes).collect())
+		.into_iter()
+		.filter_map(to_ring)
+		.map(|ring| Polygon::new(ring, vec![]))
+		.collect::<Vec<_>>();
+	if polygons.is_empty() { return None; }
+
+	for ring in merge_nodes(inner.into_iter().map(|(_, nodes)| nodes).collect()).into_iter().filter_map(to_ring) {
+		let ring = Polygon::new(ring, vec![]);
+		let Some(point) = ring.interior_point() else { continue };
+		let outer = polygons.iter_mut()
+			.filter(|p| p.contains(&point))
+			.min_by(|a, b| a.unsigned_area().total_cmp(&b.unsigned_area()));
+		if let Some(outer) = outer {
+			outer.interiors_push(ring.into_inner().0);
+		}
+	}
+
+	if polygons.len() == 1 {
+		return Some(geo::Geometry::Polygon(polygons.swap_remove(0)));
+	}
+	Some(geo::Geometry::MultiPolygon(MultiPolygon(polygons)))
+}
+
 pub fn merge_nodes(nodes: Vec<Vec<Node>>) -> Vec<Vec<Node>> {
 	/*
 		merging of nodes is necessary because ways are split into multiple groups
@@ -20,10 +54,17 @@ pub fn merge_nodes(nodes: Vec<Vec<Node>>) -> Vec<Vec<Node>> {
 		let mut path = nodes.swap_remove(0);
 
 		loop {
+			// closed ring is done, otherwise it could swallow another ring touching it
+			if path.len() > 1 && path.first().unwrap().id == path.last().unwrap().id {
+				break;
+			}
+
 			let matching_first = find_match(path.first().unwrap().id, &mut nodes);
 
 			if let Some(mut matching_nodes) = matching_first {
 				matching_nodes.reverse();
+				// shared node is already in path
+				matching_nodes.pop();
 				matching_nodes.append(&mut path);
 				path = matching_nodes;
 				continue;
@@ -32,7 +73,7 @@ pub fn merge_nodes(nodes: Vec<Vec<Node>>) -> Vec<Vec<Node>> {
 			let matching_last = find_match(path.last().unwrap().id, &mut nodes);
 
 			if let Some(mut matching_nodes) = matching_last {
-				path.append(&mut matching_nodes);
+				path.extend(matching_nodes.drain(1..));
 				continue;
 			}
 
@@ -70,3 +111,56 @@ fn find_match(node_id: NodeId, nodes: &mut Vec<Vec<Node>>) -> Option<Vec<Node>>
 
 	None
 }
+
+#[cfg(test)]
+mod tests {
+	use geo::{Area, Contains, Geometry, Point};
+	use osmpbfreader::Tags;
+
+	use super::*;
+
+	/// Node at `(lon, lat)` degrees
+	fn node(id: i64, lon: i32, lat: i32) -> Node {
+		Node { id: NodeId(id), tags: Tags::new(), decimicro_lat: lat * 10_000_000, decimicro_lon: lon * 10_000_000 }
+	}
+
+	fn member(role: &str, nodes: &[&Node]) -> (String, Vec<Node>) {
+		(role.to_string(), nodes.iter().map(|n| (*n).clone()).collect())
+	}
+
+	#[test]
+	fn two_ways_make_one_ring() {
+		let [a, b, c, d] = [node(1, 0, 0), node(2, 0, 10), node(3, 10, 10), node(4, 10, 0)];
+		let geometry = build_multipolygon(vec![member("outer", &[&a, &b, &c]), member("outer", &[&c, &d, &a])]);
+
+		let Some(Geometry::Polygon(polygon)) = geometry else { panic!("expected a polygon, got {geometry:?}") };
+		assert_eq!(polygon.exterior().0.len(), 5, "shared nodes are kept once");
+		assert!(polygon.exterior().is_closed());
+		assert_eq!(polygon.unsigned_area(), 100.0);
+		assert!(polygon.interiors().is_empty());
+	}
+
+	#[test]
+	fn inner_ring_is_a_hole_of_its_outer() {
+		// second outer touches the first one at node 1, closed rings stay apart
+		let first = [node(1, 0, 0), node(2, 0, 10), node(3, 10, 10), node(4, 10, 0)];
+		let second = [node(5, 0, -10), node(6, -10, -10), node(7, -10, 0)];
+		let hole = [node(8, 2, 2), node(9, 2, 4), node(10, 4, 4), node(11, 4, 2)];
+		let geometry = build_multipolygon(vec![
+			member("outer", &[&first[0], &first[1], &first[2], &first[3], &first[0]]),
+			member("inner", &[&hole[0], &hole[1], &hole[2]]),
+			member("outer", &[&first[0], &second[0], &second[1], &second[2], &first[0]]),
+			member("inner", &[&hole[2], &hole[3], &hole[0]]),
+		]);
+
+		let Some(Geometry::MultiPolygon(multipolygon)) = geometry else { panic!("expected a multipolygon, got {geometry:?}") };
+		assert_eq!(multipolygon.0.len(), 2);
+		let (with_hole, without_hole): (Vec<_>, Vec<_>) = multipolygon.0.iter().partition(|p| p.contains(&Point::new(5.0, 5.0)));
+		assert_eq!(with_hole.len(), 1);
+		assert_eq!(with_hole[0].interiors().len(), 1);
+		assert_eq!(with_hole[0].interiors()[0].0.len(), 5);
+		assert!(!with_hole[0].contains(&Point::new(3.0, 3.0)));
+		assert_eq!(without_hole[0].unsigned_area(), 100.0);
+		assert!(without_hole[0].interiors().is_empty());
+	}
+}
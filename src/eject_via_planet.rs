@@ -1,67 +1,140 @@
-// use std::{collections::HashMap, str::FromStr};
+use std::{
+	collections::{HashMap, HashSet},
+	fs::File,
+	io::Read,
+	path::{Path, PathBuf},
+	str::FromStr,
+	sync::Arc,
+};
 
-// use geo::Geometry;
-// use serde::Deserialize;
+use geo::Centroid;
+use geojson::{Feature, GeoJson};
+use osmpbfreader::{Node, NodeId, OsmObj, Relation, RelationId, Tags, Way, WayId};
+use parking_lot::RwLock;
 
-// use crate::{AdminRegion};
+use crate::{is_country, MappedType, Place, SyncPlace, CITY_PLACES, REGION_PLACES};
 
-// #[derive(Debug, Clone, Deserialize)]
-// pub struct PlanetExtractedAdminRegion {
-//     #[serde(deserialize_with = "deserialize_geometry")]
-//     pub geometry: Geometry<f64>,
+/// Properties added by `extract_polygons` and `Place::to_feature`, everything else is OSM tags
+static DERIVED_PROPERTIES: &[&str] = &["osm_url", "center", "center_role", "mapped_type", "parents"];
 
-//     #[serde(flatten)]
-//     pub tags: HashMap<String, String>,
+/// Places without hierarchy from `extract_polygons` output: a directory of `.geojson` or `.geojson.gz` files (nested ones too, like `geojson_tree`)
+/// or a single file, each holding a Feature or a FeatureCollection. Directories written with several formats have every place more than once,
+/// the first file in path order having a feature id wins
+pub fn load(path: &Path) -> anyhow::Result<Vec<SyncPlace>> {
+	let mut files = Vec::new();
+	match path.is_dir() {
+		true => geojson_files(path, &mut files)?,
+		false => files.push(path.to_path_buf()),
+	}
+	files.sort();
 
-//     pub name: String,
-//     pub admin_level: u8,
-// }
+	let mut places = Vec::new();
+	let (mut skipped, mut duplicates) = (0, 0);
+	let mut seen = HashSet::new();
+	for file in files.iter() {
+		let content = read_geojson(file).map_err(|e| anyhow::anyhow!("can't read {}: {e}", file.display()))?;
+		let features = match GeoJson::from_str(&content).map_err(|e| anyhow::anyhow!("bad geojson {}: {e}", file.display()))? {
+			GeoJson::Feature(feature) => vec![feature],
+			GeoJson::FeatureCollection(collection) => collection.features,
+			GeoJson::Geometry(_) => anyhow::bail!("{} has bare geometry instead of features", file.display()),
+		};
+		for feature in features {
+			if let Some(geojson::feature::Id::String(id)) = &feature.id && !seen.insert(id.clone()) {
+				duplicates += 1;
+				continue;
+			}
+			match place(feature) {
+				Some(place) => places.push(Arc::new(RwLock::new(place))),
+				None => skipped += 1,
+			}
+		}
+	}
+	println!("loaded {} places from {} files, skipped {skipped} features without id, name, geometry or mapped type and {duplicates} duplicates", places.len(), files.len());
+	Ok(places)
+}
 
-// impl AdminRegion for PlanetExtractedAdminRegion {
-//     fn geometry(&self) -> &Geometry<f64> {
-//         &self.geometry
-//     }
-//     fn name(&self) -> &String {
-//         &self.name
-//     }
-//     fn admin_level(&self) -> u8 {
-//         self.admin_level
-//     }
-//     fn tags(&self) -> &HashMap<String, String> {
-//         &self.tags
-//     }
-// }
+fn geojson_files(dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+	for entry in std::fs::read_dir(dir)? {
+		let path = entry?.path();
+		if path.is_dir() {
+			geojson_files(&path, files)?;
+		}
+		else if [".geojson", ".geojson.gz"].iter().any(|ext| path.to_string_lossy().ends_with(ext)) {
+			files.push(path);
+		}
+	}
+	Ok(())
+}
 
-// pub fn load_planet_extracted_places() -> anyhow::Result<Vec<PlanetExtractedAdminRegion>> {
-//     let str = std::fs::read_to_string("./output/extracted/places.geojson")?;
-//     let geojson = geojson::FeatureCollection::from_str(&str)?;
+/// `OUTPUT_GZIP=1` output is gzipped
+fn read_geojson(path: &Path) -> anyhow::Result<String> {
+	let mut content = String::new();
+	match path.to_string_lossy().ends_with(".gz") {
+		true => flate2::read::MultiGzDecoder::new(File::open(path)?).read_to_string(&mut content)?,
+		false => File::open(path)?.read_to_string(&mut content)?,
+	};
+	Ok(content)
+}
 
-//     let mut output = Vec::with_capacity(geojson.features.len());
+/// `mapped_type` property when the feature comes from pipeline1, otherwise by tags like pipeline1 picks places
+fn mapped_type(properties: &geojson::JsonObject, tags: &Tags) -> Option<MappedType> {
+	match properties.get("mapped_type").and_then(|t| t.as_str()) {
+		Some("country") => return Some(MappedType::Country),
+		Some("region") => return Some(MappedType::Region),
+		Some("city") => return Some(MappedType::City),
+		_ => {},
+	}
+	if is_country(tags) {
+		return Some(MappedType::Country);
+	}
+	if tags.get("place").is_some_and(|place| REGION_PLACES.contains(&place.as_str())) || ["3", "4"].iter().any(|al| tags.contains("admin_level", al)) {
+		return Some(MappedType::Region);
+	}
+	if tags.get("place").is_some_and(|place| CITY_PLACES.contains(&place.as_str())) || ["8", "9"].iter().any(|al| tags.contains("admin_level", al)) {
+		return Some(MappedType::City);
+	}
+	None
+}
 
-//     for feature in geojson.features {
-//         let properties: HashMap<String, String> =
-//             serde_json::from_value(serde_json::to_value(feature.properties.as_ref().unwrap())?)?;
+/// Feature with id like `relation/123/Name` into a place, its source is the element with tags but without members
+fn place(feature: Feature) -> Option<Place> {
+	let Some(geojson::feature::Id::String(id)) = feature.id else { return None };
+	let properties = feature.properties?;
+	let geometry = geo::Geometry::try_from(feature.geometry?.value).ok()?;
 
-//         let Some(admin_level) = properties
-//             .get("admin_level")
-//             .and_then(|s| u8::from_str(s).ok())
-//         else {
-//             eprintln!("no admin_level in {:#?}", feature);
-//             continue;
-//         };
+	let tags = properties.iter()
+		.filter(|(key, _)| !DERIVED_PROPERTIES.contains(&key.as_str()))
+		.filter_map(|(key, value)| match value {
+			serde_json::Value::Null => None,
+			serde_json::Value::String(s) => Some((key.clone(), s.clone())),
+			value => Some((key.clone(), value.to_string())),
+		})
+		.collect::<HashMap<_, _>>();
+	if !tags.contains_key("name") {
+		return None;
+	}
+	let osm_tags = tags.iter().map(|(k, v)| (k.as_str().into(), v.as_str().into())).collect::<Tags>();
 
-//         let Some(name) = properties.get("name").map(ToOwned::to_owned) else {
-//             eprintln!("no name in {:#?}", feature);
-//             continue;
-//         };
+	let (center, center_role) = properties.get("center").and_then(|c| c.as_str())
+		.and_then(|c| c.split_once(','))
+		.and_then(|(x, y)| Some(geo::Coord { x: x.parse::<f64>().ok()?, y: y.parse::<f64>().ok()? }))
+		.map(|center| (center, properties.get("center_role").and_then(|r| r.as_str()).unwrap_or("unknown").to_string()))
+		.or_else(|| geometry.centroid().map(|c| (c.into(), "centroid".to_string())))?;
 
-//         output.push(PlanetExtractedAdminRegion {
-//             name,
-//             admin_level,
-//             geometry: feature.geometry.unwrap().try_into()?,
-//             tags: properties,
-//         });
-//     }
+	let mut parts = id.splitn(3, '/');
+	let (kind, osm_id) = (parts.next()?, parts.next()?.parse::<i64>().ok()?);
+	let mapped_type = mapped_type(&properties, &osm_tags)?;
+	let source = match kind {
+		"relation" => OsmObj::Relation(Relation { id: RelationId(osm_id), tags: osm_tags, refs: vec![] }),
+		"way" => OsmObj::Way(Way { id: WayId(osm_id), tags: osm_tags, nodes: vec![] }),
+		"node" => OsmObj::Node(Node {
+			id: NodeId(osm_id),
+			tags: osm_tags,
+			decimicro_lat: (center.y * 1e7).round() as i32,
+			decimicro_lon: (center.x * 1e7).round() as i32,
+		}),
+		_ => return None,
+	};
 
-//     Ok(output)
-// }
+	Some(Place { mapped_type, parents: vec![], geometry, tags, center, center_role, source })
+}
@@ -0,0 +1,144 @@
+use std::path::Path;
+
+use serde::Deserialize;
+
+use super::{HierarchyRow, Tier};
+
+/// Columns of a hierarchy table. Default spec gives the classic `table.csv`:
+/// `country_iso,country_wikidata,country_en,country_ru,region_iso,region_ref,region_wikidata,region_en,region_ru,city_wikidata,city_en,city_ru,lon,lat,population`
+#[derive(Deserialize, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TableSpec {
+	/// file name inside the artifacts dir
+	pub file: String,
+	/// `<tier>_<lang>` columns from `name:<lang>` tags
+	pub languages: Vec<String>,
+	/// languages that fall back to plain `name` when `name:<lang>` is missing
+	pub name_fallback: Vec<String>,
+	/// tags of the place itself, written after `lon,lat` with the tag key as a header
+	pub tags: Vec<String>,
+	/// `<tier>_id` columns with osm ids like `relation/123`
+	pub ids: bool,
+	/// `parent_ids` column, `;` separated osm ids of direct parents
+	pub parent_ids: bool,
+}
+
+impl Default for TableSpec {
+	fn default() -> Self {
+		Self {
+			file: "table.csv".into(),
+			languages: vec!["en".into(), "ru".into()],
+			name_fallback: vec!["en".into()],
+			tags: vec!["population".into()],
+			ids: false,
+			parent_ids: false,
+		}
+	}
+}
+
+enum Column<'a> {
+	Id(Tier),
+	Iso(Tier),
+	Ref(Tier),
+	Wikidata(Tier),
+	Name(Tier, &'a str),
+	Lon,
+	Lat,
+	Tag(&'a str),
+	ParentIds,
+}
+
+fn tier_prefix(tier: Tier) -> &'static str {
+	match tier {
+		Tier::Country => "country",
+		Tier::Region => "region",
+		Tier::District => "district",
+		Tier::City => "city",
+	}
+}
+
+impl TableSpec {
+	fn columns(&self) -> Vec<Column<'_>> {
+		let mut columns = Vec::new();
+		for tier in [Tier::Country, Tier::Region, Tier::City] {
+			if self.ids {
+				columns.push(Column::Id(tier));
+			}
+			if tier != Tier::City {
+				columns.push(Column::Iso(tier));
+			}
+			if tier == Tier::Region {
+				columns.push(Column::Ref(tier));
+			}
+			columns.push(Column::Wikidata(tier));
+			columns.extend(self.languages.iter().map(|lang| Column::Name(tier, lang)));
+		}
+		columns.extend([Column::Lon, Column::Lat]);
+		columns.extend(self.tags.iter().map(|tag| Column::Tag(tag)));
+		if self.parent_ids {
+			columns.push(Column::ParentIds);
+		}
+		columns
+	}
+}
+
+impl Column<'_> {
+	fn header(&self) -> String {
+		match self {
+			Column::Id(tier) => format!("{}_id", tier_prefix(*tier)),
+			Column::Iso(tier) => format!("{}_iso", tier_prefix(*tier)),
+			Column::Ref(tier) => format!("{}_ref", tier_prefix(*tier)),
+			Column::Wikidata(tier) => format!("{}_wikidata", tier_prefix(*tier)),
+			Column::Name(tier, lang) => format!("{}_{lang}", tier_prefix(*tier)),
+			Column::Lon => "lon".into(),
+			Column::Lat => "lat".into(),
+			Column::Tag(tag) => tag.to_string(),
+			Column::ParentIds => "parent_ids".into(),
+		}
+	}
+
+	fn value(&self, spec: &TableSpec, row: &HierarchyRow) -> String {
+		let value = match self {
+			Column::Id(tier) => row.item(*tier).map(|i| &i.osm_id),
+			Column::Iso(tier) => row.item(*tier).and_then(|i| i.iso_code.as_ref()),
+			Column::Ref(tier) => row.item(*tier).and_then(|i| i.osm_ref.as_ref()),
+			Column::Wikidata(tier) => row.item(*tier).and_then(|i| i.wikidata.as_ref()),
+			Column::Name(tier, lang) => row.item(*tier).and_then(|i| i.name(lang, spec.name_fallback.iter().any(|l| l == lang))),
+			Column::Lon => return row.lon.to_string(),
+			Column::Lat => return row.lat.to_string(),
+			Column::Tag(tag) => row.tags.get(*tag),
+			Column::ParentIds => return row.parent_ids.join(";"),
+		};
+		value.cloned().unwrap_or_default()
+	}
+}
+
+/// Specs from comma separated json files in `CSV_SPECS`, one table per file. Only the default table without it
+pub fn table_specs_from_env() -> anyhow::Result<Vec<TableSpec>> {
+	let Ok(paths) = std::env::var("CSV_SPECS") else { return Ok(vec![TableSpec::default()]) };
+
+	let mut specs = Vec::<TableSpec>::new();
+	for path in paths.split(',').map(|p| p.trim()).filter(|p| !p.is_empty()) {
+		let spec: TableSpec = std::fs::read_to_string(path)
+			.map_err(anyhow::Error::from)
+			.and_then(|spec| Ok(serde_json::from_str(&spec)?))
+			.map_err(|e| anyhow::anyhow!("bad csv spec {path}: {e}"))?;
+		if specs.iter().any(|s| s.file == spec.file) {
+			anyhow::bail!("csv spec {path} writes {} like one of previous specs", spec.file);
+		}
+		specs.push(spec);
+	}
+	Ok(specs)
+}
+
+/// RFC 4180 table with columns from `spec`
+pub fn write_table(dir: &Path, spec: &TableSpec, rows: &[HierarchyRow]) -> anyhow::Result<()> {
+	let columns = spec.columns();
+	let mut writer = ::csv::WriterBuilder::new().terminator(::csv::Terminator::CRLF).from_path(dir.join(&spec.file))?;
+	writer.write_record(columns.iter().map(|c| c.header()))?;
+	for row in rows {
+		writer.write_record(columns.iter().map(|c| c.value(spec, row)))?;
+	}
+	writer.flush()?;
+	Ok(())
+}
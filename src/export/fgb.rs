@@ -0,0 +1,486 @@
+// https://flatgeobuf.org
+// https://github.com/flatgeobuf/flatgeobuf/blob/master/src/fbs/header.fbs
+// https://github.com/flatgeobuf/flatgeobuf/blob/master/src/fbs/feature.fbs
+
+use std::{
+	collections::HashMap,
+	fs::File,
+	io::{BufWriter, Read, Seek, SeekFrom, Write},
+	path::{Path, PathBuf},
+};
+
+use flatbuffers::{FlatBufferBuilder, TableFinishedWIPOffset, WIPOffset};
+use geo::{BoundingRect, Geometry};
+use ::geojson::Feature;
+use parking_lot::Mutex;
+
+use super::{feature_geometry, property_string, FeatureSink};
+
+const MAGIC: [u8; 8] = [b'f', b'g', b'b', 3, b'f', b'g', b'b', 1];
+const INDEX_NODE_SIZE: u16 = 16;
+
+const GEOMETRY_TYPE_POINT: u8 = 1;
+const GEOMETRY_TYPE_POLYGON: u8 = 3;
+const GEOMETRY_TYPE_MULTIPOLYGON: u8 = 6;
+const COLUMN_TYPE_STRING: u8 = 11;
+
+// flatbuffers vtable slot of n-th field is 4 + 2 * n
+const fn slot(field: u16) -> u16 {
+	4 + 2 * field
+}
+
+#[derive(Clone, Copy)]
+struct NodeItem {
+	min_x: f64,
+	min_y: f64,
+	max_x: f64,
+	max_y: f64,
+	offset: u64,
+}
+
+impl NodeItem {
+	fn empty(offset: u64) -> Self {
+		Self { min_x: f64::INFINITY, min_y: f64::INFINITY, max_x: f64::NEG_INFINITY, max_y: f64::NEG_INFINITY, offset }
+	}
+
+	fn expand(&mut self, other: &NodeItem) {
+		self.min_x = self.min_x.min(other.min_x);
+		self.min_y = self.min_y.min(other.min_y);
+		self.max_x = self.max_x.max(other.max_x);
+		self.max_y = self.max_y.max(other.max_y);
+	}
+
+	fn write(&self, out: &mut impl Write) -> std::io::Result<()> {
+		out.write_all(&self.min_x.to_le_bytes())?;
+		out.write_all(&self.min_y.to_le_bytes())?;
+		out.write_all(&self.max_x.to_le_bytes())?;
+		out.write_all(&self.max_y.to_le_bytes())?;
+		out.write_all(&self.offset.to_le_bytes())
+	}
+}
+
+/// Feature already encoded into the spool file, waiting for its place in hilbert order
+struct SpooledFeature {
+	bbox: NodeItem,
+	spool_offset: u64,
+	len: u64,
+}
+
+struct Layer {
+	geometry_type: u8,
+	spool_path: PathBuf,
+	spool: BufWriter<File>,
+	spool_len: u64,
+	features: Vec<SpooledFeature>,
+}
+
+#[derive(Default)]
+struct State {
+	columns: Vec<String>,
+	column_ids: HashMap<String, u16>,
+	layers: HashMap<&'static str, Layer>,
+}
+
+/// Writes polygons into `boundaries.fgb` (as MultiPolygon) and points into `points.fgb`, both with packed hilbert R-tree.
+/// Every feature property becomes a string column. Features are spooled to disk until `finish`,
+/// because the index and the header (columns, count, envelope) go before them
+pub struct FlatGeobufSink {
+	dir: PathBuf,
+	state: Mutex<State>,
+}
+
+impl FlatGeobufSink {
+	pub fn new(dir: &Path) -> Self {
+		Self { dir: dir.to_path_buf(), state: Mutex::new(State::default()) }
+	}
+}
+
+impl FeatureSink for FlatGeobufSink {
+	fn write(&self, feature: &Feature) -> anyhow::Result<()> {
+		let Some(geometry) = feature_geometry(feature) else { return Ok(()) };
+		let (layer_name, geometry_type) = match geometry {
+			Geometry::Point(_) => ("points", GEOMETRY_TYPE_POINT),
+			Geometry::Polygon(_) | Geometry::MultiPolygon(_) => ("boundaries", GEOMETRY_TYPE_MULTIPOLYGON),
+			_ => {
+				eprintln!("flatgeobuf: skipping unsupported geometry of {:?}", feature.id);
+				return Ok(());
+			}
+		};
+		let Some(rect) = geometry.bounding_rect() else { return Ok(()) };
+
+		let properties = feature.properties_iter().map(|(k, v)| (k.clone(), property_string(v))).collect::<Vec<_>>();
+		let column_ids = {
+			let mut state = self.state.lock();
+			let mut ids = Vec::with_capacity(properties.len());
+			for (key, _) in properties.iter() {
+				let id = match state.column_ids.get(key) {
+					Some(id) => *id,
+					None => {
+						let id = u16::try_from(state.columns.len()).map_err(|_| anyhow::anyhow!("flatgeobuf: too many columns"))?;
+						state.columns.push(key.clone());
+						state.column_ids.insert(key.clone(), id);
+						id
+					}
+				};
+				ids.push(id);
+			}
+			ids
+		};
+
+		let mut encoded_properties = Vec::with_capacity(properties.iter().map(|(_, v)| v.len() + 6).sum());
+		for ((_, value), id) in properties.iter().zip(column_ids) {
+			encoded_properties.extend_from_slice(&id.to_le_bytes());
+			encoded_properties.extend_from_slice(&(value.len() as u32).to_le_bytes());
+			encoded_properties.extend_from_slice(value.as_bytes());
+		}
+
+		let mut fbb = FlatBufferBuilder::with_capacity(1024);
+		let geometry = build_geometry(&mut fbb, &geometry);
+		let properties = fbb.create_vector(&encoded_properties);
+		let table = fbb.start_table();
+		fbb.push_slot_always(slot(0), geometry);
+		fbb.push_slot_always(slot(1), properties);
+		let table = fbb.end_table(table);
+		fbb.finish_size_prefixed(table, None);
+		let data = fbb.finished_data();
+
+		let bbox = NodeItem { min_x: rect.min().x, min_y: rect.min().y, max_x: rect.max().x, max_y: rect.max().y, offset: 0 };
+
+		let mut state = self.state.lock();
+		if !state.layers.contains_key(layer_name) {
+			let spool_path = self.dir.join(format!("{layer_name}.fgb.spool"));
+			let spool = BufWriter::new(File::create(&spool_path)?);
+			state.layers.insert(layer_name, Layer { geometry_type, spool_path, spool, spool_len: 0, features: Vec::new() });
+		}
+		let layer = state.layers.get_mut(layer_name).unwrap();
+		layer.spool.write_all(data)?;
+		layer.features.push(SpooledFeature { bbox, spool_offset: layer.spool_len, len: data.len() as u64 });
+		layer.spool_len += data.len() as u64;
+
+		Ok(())
+	}
+
+	fn finish(&self) -> anyhow::Result<()> {
+		let mut state = self.state.lock();
+		let columns = std::mem::take(&mut state.columns);
+		for (name, layer) in state.layers.drain() {
+			let count = layer.features.len();
+			write_layer(&self.dir.join(format!("{name}.fgb")), name, &columns, layer)?;
+			println!("written {count} features to {name}.fgb");
+		}
+		Ok(())
+	}
+}
+
+fn build_geometry<'a>(fbb: &mut FlatBufferBuilder<'a>, geometry: &Geometry) -> WIPOffset<TableFinishedWIPOffset> {
+	match geometry {
+		Geometry::Point(point) => {
+			let xy = fbb.create_vector(&[point.x(), point.y()]);
+			let table = fbb.start_table();
+			fbb.push_slot_always(slot(1), xy);
+			fbb.push_slot(slot(6), GEOMETRY_TYPE_POINT, 0);
+			fbb.end_table(table)
+		},
+		Geometry::Polygon(polygon) => build_geometry(fbb, &Geometry::MultiPolygon(geo::MultiPolygon(vec![polygon.clone()]))),
+		Geometry::MultiPolygon(mp) => {
+			let parts = mp.0.iter().map(|polygon| {
+				let mut ends = Vec::with_capacity(polygon.interiors().len() + 1);
+				let mut xy = Vec::new();
+				for ring in std::iter::once(polygon.exterior()).chain(polygon.interiors()) {
+					xy.extend(ring.coords().flat_map(|c| [c.x, c.y]));
+					ends.push((xy.len() / 2) as u32);
+				}
+				let ends = fbb.create_vector(&ends);
+				let xy = fbb.create_vector(&xy);
+				let table = fbb.start_table();
+				fbb.push_slot_always(slot(0), ends);
+				fbb.push_slot_always(slot(1), xy);
+				fbb.push_slot(slot(6), GEOMETRY_TYPE_POLYGON, 0);
+				fbb.end_table(table)
+			}).collect::<Vec<_>>();
+			let parts = fbb.create_vector(&parts);
+			let table = fbb.start_table();
+			fbb.push_slot(slot(6), GEOMETRY_TYPE_MULTIPOLYGON, 0);
+			fbb.push_slot_always(slot(7), parts);
+			fbb.end_table(table)
+		},
+		_ => unreachable!("filtered out in FlatGeobufSink::write"),
+	}
+}
+
+fn build_header(name: &str, columns: &[String], geometry_type: u8, features_count: u64, extent: &NodeItem) -> Vec<u8> {
+	let mut fbb = FlatBufferBuilder::with_capacity(4096);
+
+	let columns = columns.iter().map(|column| {
+		let name = fbb.create_string(column);
+		let table = fbb.start_table();
+		fbb.push_slot_always(slot(0), name);
+		fbb.push_slot(slot(1), COLUMN_TYPE_STRING, 0);
+		fbb.end_table(table)
+	}).collect::<Vec<_>>();
+	let columns = fbb.create_vector(&columns);
+
+	let crs = {
+		let org = fbb.create_string("EPSG");
+		let table = fbb.start_table();
+		fbb.push_slot_always(slot(0), org);
+		fbb.push_slot(slot(1), 4326i32, 0);
+		fbb.end_table(table)
+	};
+	let name = fbb.create_string(name);
+	let envelope = fbb.create_vector(&[extent.min_x, extent.min_y, extent.max_x, extent.max_y]);
+
+	let table = fbb.start_table();
+	fbb.push_slot_always(slot(0), name);
+	if features_count > 0 {
+		fbb.push_slot_always(slot(1), envelope);
+	}
+	fbb.push_slot(slot(2), geometry_type, 0);
+	fbb.push_slot_always(slot(7), columns);
+	fbb.push_slot(slot(8), features_count, 0);
+	fbb.push_slot(slot(9), INDEX_NODE_SIZE, 16);
+	fbb.push_slot_always(slot(10), crs);
+	let table = fbb.end_table(table);
+	fbb.finish_size_prefixed(table, None);
+
+	fbb.finished_data().to_vec()
+}
+
+fn write_layer(path: &Path, name: &str, columns: &[String], mut layer: Layer) -> anyhow::Result<()> {
+	layer.spool.flush()?;
+	drop(layer.spool);
+
+	let mut extent = NodeItem::empty(0);
+	for feature in layer.features.iter() {
+		extent.expand(&feature.bbox);
+	}
+	hilbert_sort(&mut layer.features, &extent);
+
+	let mut offset = 0;
+	for feature in layer.features.iter_mut() {
+		feature.bbox.offset = offset;
+		offset += feature.len;
+	}
+
+	let mut out = BufWriter::new(File::create(path)?);
+	out.write_all(&MAGIC)?;
+	out.write_all(&build_header(name, columns, layer.geometry_type, layer.features.len() as u64, &extent))?;
+
+	if !layer.features.is_empty() {
+		for node in build_packed_rtree(&layer.features.iter().map(|f| f.bbox).collect::<Vec<_>>()) {
+			node.write(&mut out)?;
+		}
+	}
+
+	let mut spool = File::open(&layer.spool_path)?;
+	let mut buf = Vec::new();
+	for feature in layer.features.iter() {
+		buf.resize(feature.len as usize, 0);
+		spool.seek(SeekFrom::Start(feature.spool_offset))?;
+		spool.read_exact(&mut buf)?;
+		out.write_all(&buf)?;
+	}
+	out.flush()?;
+	std::fs::remove_file(&layer.spool_path)?;
+
+	Ok(())
+}
+
+/// Node ranges of every tree level, leaves first. Levels are stored root first in the file
+fn level_bounds(items_count: usize, node_size: usize) -> Vec<std::ops::Range<usize>> {
+	let mut level_nodes_count = vec![items_count];
+	let mut n = items_count;
+	let mut nodes_count = n;
+	loop {
+		n = n.div_ceil(node_size);
+		nodes_count += n;
+		level_nodes_count.push(n);
+		if n == 1 { break; }
+	}
+
+	let mut bounds = Vec::with_capacity(level_nodes_count.len());
+	let mut end = nodes_count;
+	for count in level_nodes_count {
+		bounds.push(end - count..end);
+		end -= count;
+	}
+	bounds
+}
+
+fn build_packed_rtree(leaves: &[NodeItem]) -> Vec<NodeItem> {
+	let node_size = INDEX_NODE_SIZE as usize;
+	let bounds = level_bounds(leaves.len(), node_size);
+	let mut nodes = vec![NodeItem::empty(0); bounds[0].end];
+	nodes[bounds[0].clone()].copy_from_slice(leaves);
+
+	for level in 0..bounds.len() - 1 {
+		let children = bounds[level].clone();
+		let mut parent_idx = bounds[level + 1].start;
+		let mut child_idx = children.start;
+		while child_idx < children.end {
+			let mut parent = NodeItem::empty(child_idx as u64);
+			for _ in 0..node_size {
+				if child_idx >= children.end { break; }
+				parent.expand(&nodes[child_idx]);
+				child_idx += 1;
+			}
+			nodes[parent_idx] = parent;
+			parent_idx += 1;
+		}
+	}
+	nodes
+}
+
+fn hilbert_sort(features: &mut [SpooledFeature], extent: &NodeItem) {
+	const HILBERT_MAX: f64 = ((1 << 16) - 1) as f64;
+	let width = extent.max_x - extent.min_x;
+	let height = extent.max_y - extent.min_y;
+
+	features.sort_by_cached_key(|f| {
+		let x = if width == 0.0 { 0 } else { (HILBERT_MAX * ((f.bbox.min_x + f.bbox.max_x) / 2.0 - extent.min_x) / width).floor() as u32 };
+		let y = if height == 0.0 { 0 } else { (HILBERT_MAX * ((f.bbox.min_y + f.bbox.max_y) / 2.0 - extent.min_y) / height).floor() as u32 };
+		std::cmp::Reverse(hilbert(x, y))
+	});
+}
+
+// https://github.com/rawrunprotected/hilbert_curves (public domain), same as flatbush/flatgeobuf use
+fn hilbert(x: u32, y: u32) -> u32 {
+	let mut a = x ^ y;
+	let mut b = 0xFFFF ^ a;
+	let mut c = 0xFFFF ^ (x | y);
+	let mut d = x & (y ^ 0xFFFF);
+
+	let mut aa = a | (b >> 1);
+	let mut bb = (a >> 1) ^ a;
+	let mut cc = ((c >> 1) ^ (b & (d >> 1))) ^ c;
+	let mut dd = ((a & (c >> 1)) ^ (d >> 1)) ^ d;
+
+	a = aa; b = bb; c = cc; d = dd;
+	aa = (a & (a >> 2)) ^ (b & (b >> 2));
+	bb = (a & (b >> 2)) ^ (b & ((a ^ b) >> 2));
+	cc ^= (a & (c >> 2)) ^ (b & (d >> 2));
+	dd ^= (b & (c >> 2)) ^ ((a ^ b) & (d >> 2));
+
+	a = aa; b = bb; c = cc; d = dd;
+	aa = (a & (a >> 4)) ^ (b & (b >> 4));
+	bb = (a & (b >> 4)) ^ (b & ((a ^ b) >> 4));
+	cc ^= (a & (c >> 4)) ^ (b & (d >> 4));
+	dd ^= (b & (c >> 4)) ^ ((a ^ b) & (d >> 4));
+
+	a = aa; b = bb; c = cc; d = dd;
+	cc ^= (a & (c >> 8)) ^ (b & (d >> 8));
+	dd ^= (b & (c >> 8)) ^ ((a ^ b) & (d >> 8));
+
+	a = cc ^ (cc >> 1);
+	b = dd ^ (dd >> 1);
+
+	let mut i0 = x ^ y;
+	let mut i1 = b | (0xFFFF ^ (i0 | a));
+
+	i0 = (i0 | (i0 << 8)) & 0x00FF00FF;
+	i0 = (i0 | (i0 << 4)) & 0x0F0F0F0F;
+	i0 = (i0 | (i0 << 2)) & 0x33333333;
+	i0 = (i0 | (i0 << 1)) & 0x55555555;
+
+	i1 = (i1 | (i1 << 8)) & 0x00FF00FF;
+	i1 = (i1 | (i1 << 4)) & 0x0F0F0F0F;
+	i1 = (i1 | (i1 << 2)) & 0x33333333;
+	i1 = (i1 | (i1 << 1)) & 0x55555555;
+
+	(i1 << 1) | i0
+}
+
+#[cfg(test)]
+mod tests {
+	use flatbuffers::{ForwardsUOffset, Table, Vector};
+
+	use super::*;
+
+	fn point(name: &str, x: f64, y: f64) -> Feature {
+		serde_json::from_value(serde_json::json!({
+			"type": "Feature",
+			"id": format!("node/1/{name}"),
+			"geometry": { "type": "Point", "coordinates": [x, y] },
+			"properties": { "name": name, "place": "city" },
+		})).unwrap()
+	}
+
+	fn size_prefixed_table(data: &[u8]) -> (Table<'_>, usize) {
+		let len = u32::from_le_bytes(data[..4].try_into().unwrap()) as usize;
+		(unsafe { flatbuffers::size_prefixed_root_unchecked::<Table>(&data[..4 + len]) }, 4 + len)
+	}
+
+	fn node_item(data: &[u8]) -> NodeItem {
+		let f = |i: usize| f64::from_le_bytes(data[i * 8..i * 8 + 8].try_into().unwrap());
+		NodeItem { min_x: f(0), min_y: f(1), max_x: f(2), max_y: f(3), offset: u64::from_le_bytes(data[32..40].try_into().unwrap()) }
+	}
+
+	#[test]
+	fn hilbert_matches_textbook_curve() {
+		// d of (x, y) on a 65536 x 65536 grid, from the xy2d algorithm in https://en.wikipedia.org/wiki/Hilbert_curve
+		for (x, y, d) in [(0, 0, 0), (1, 0, 1), (0, 65535, 1431655765), (65535, 65535, 2863311530), (65535, 0, 4294967295), (12345, 54321, 1555040834)] {
+			assert_eq!(hilbert(x, y), d, "({x}, {y})");
+		}
+	}
+
+	#[test]
+	fn level_bounds_are_root_last() {
+		assert_eq!(level_bounds(20, 16), vec![3..23, 1..3, 0..1]);
+		assert_eq!(level_bounds(16, 16), vec![1..17, 0..1]);
+		assert_eq!(level_bounds(1, 16), vec![1..2, 0..1]);
+	}
+
+	#[test]
+	fn writes_header_index_and_features_in_hilbert_order() {
+		let dir = std::env::temp_dir().join(format!("rs-osm-fgb-test-{}", std::process::id()));
+		std::fs::create_dir_all(&dir).unwrap();
+		let points = [("a", 10.0, 50.0), ("b", -5.0, 40.0), ("c", 30.0, 60.0), ("d", 0.0, 45.0)];
+		let sink = FlatGeobufSink::new(&dir);
+		for (name, x, y) in points {
+			sink.write(&point(name, x, y)).unwrap();
+		}
+		sink.finish().unwrap();
+		let data = std::fs::read(dir.join("points.fgb")).unwrap();
+		assert!(!dir.join("points.fgb.spool").exists());
+		std::fs::remove_dir_all(&dir).unwrap();
+
+		assert_eq!(data[..8], MAGIC);
+		let (header, header_len) = size_prefixed_table(&data[8..]);
+		let columns = unsafe {
+			assert_eq!(header.get::<ForwardsUOffset<&str>>(slot(0), None), Some("points"));
+			assert_eq!(header.get::<u8>(slot(2), Some(0)), Some(GEOMETRY_TYPE_POINT));
+			assert_eq!(header.get::<u64>(slot(8), Some(0)), Some(points.len() as u64));
+			assert_eq!(header.get::<u16>(slot(9), Some(16)), Some(INDEX_NODE_SIZE));
+			let envelope = header.get::<ForwardsUOffset<Vector<f64>>>(slot(1), None).unwrap();
+			assert_eq!(envelope.iter().collect::<Vec<_>>(), vec![-5.0, 40.0, 30.0, 60.0]);
+			header.get::<ForwardsUOffset<Vector<ForwardsUOffset<Table>>>>(slot(7), None).unwrap().iter()
+				.map(|column| column.get::<ForwardsUOffset<&str>>(slot(0), None).unwrap().to_string())
+				.collect::<Vec<_>>()
+		};
+		assert_eq!(columns, vec!["name", "place"]);
+
+		// one leaf level under the root
+		let index = &data[8 + header_len..];
+		let nodes = (0..points.len() + 1).map(|i| node_item(&index[i * 40..])).collect::<Vec<_>>();
+		let root = nodes[0];
+		assert_eq!((root.min_x, root.min_y, root.max_x, root.max_y, root.offset), (-5.0, 40.0, 30.0, 60.0, 1));
+
+		// descending distances along the curve over the extent, from the textbook xy2d: c 2863311530, a 722383083, d 157652569, b 0
+		let expected = [("c", 30.0, 60.0), ("a", 10.0, 50.0), ("d", 0.0, 45.0), ("b", -5.0, 40.0)];
+
+		let features = &index[nodes.len() * 40..];
+		let mut offset = 0;
+		for (leaf, (name, x, y)) in nodes[1..].iter().zip(expected) {
+			assert_eq!((leaf.min_x, leaf.min_y, leaf.max_x, leaf.max_y), (x, y, x, y));
+			assert_eq!(leaf.offset, offset as u64);
+			let (feature, len) = size_prefixed_table(&features[offset..]);
+			let properties = unsafe { feature.get::<ForwardsUOffset<Vector<u8>>>(slot(1), None).unwrap().bytes() };
+			// `name` is column 0 and goes first
+			assert_eq!(u16::from_le_bytes(properties[..2].try_into().unwrap()), 0);
+			let value_len = u32::from_le_bytes(properties[2..6].try_into().unwrap()) as usize;
+			assert_eq!(&properties[6..6 + value_len], name.as_bytes());
+			offset += len;
+		}
+		assert_eq!(offset, features.len());
+	}
+}
@@ -0,0 +1,140 @@
+use std::{
+	collections::HashMap,
+	io::Write,
+	path::{Path, PathBuf},
+};
+
+use ::geojson::Feature;
+use parking_lot::Mutex;
+
+use super::{level_key, safe_file_name, FeatureSink, Output};
+
+/// Writes every feature into its own `<safe id>.geojson` file
+pub struct GeoJsonFilesSink {
+	dir: PathBuf,
+}
+
+impl GeoJsonFilesSink {
+	pub fn new(dir: &Path) -> Self {
+		Self { dir: dir.to_path_buf() }
+	}
+}
+
+impl FeatureSink for GeoJsonFilesSink {
+	fn write(&self, feature: &Feature) -> anyhow::Result<()> {
+		let id = match feature.id.clone() {
+			Some(::geojson::feature::Id::String(s)) => s,
+			_ => anyhow::bail!("feature without string id"),
+		};
+		std::fs::write(self.dir.join(format!("{}.geojson", safe_file_name(&id))), feature.to_string())?;
+		Ok(())
+	}
+
+	fn finish(&self) -> anyhow::Result<()> {
+		Ok(())
+	}
+}
+
+/// Streams features into a single FeatureCollection, only the current feature is kept in memory
+pub struct FeatureCollectionWriter {
+	out: Output,
+	written: u64,
+}
+
+impl FeatureCollectionWriter {
+	pub fn create(path: &Path, gzip: bool) -> anyhow::Result<Self> {
+		let mut out = Output::create(path, gzip)?;
+		out.write_all(br#"{"type":"FeatureCollection","features":["#)?;
+		Ok(Self { out, written: 0 })
+	}
+
+	/// Appends already serialized feature, lets callers serialize outside of a lock
+	pub fn write_serialized(&mut self, feature: &[u8]) -> anyhow::Result<()> {
+		if self.written > 0 {
+			self.out.write_all(b",")?;
+		}
+		self.out.write_all(b"\n")?;
+		self.out.write_all(feature)?;
+		self.written += 1;
+		Ok(())
+	}
+
+	/// Closes the collection, returns count of written features
+	pub fn finish(mut self) -> anyhow::Result<u64> {
+		self.out.write_all(b"\n]}\n")?;
+		self.out.finish()?;
+		Ok(self.written)
+	}
+}
+
+/// Writes all features into `features.geojson` or, with `per_level`, into `<level key>.geojson` per admin_level/place
+pub struct FeatureCollectionSink {
+	dir: PathBuf,
+	gzip: bool,
+	per_level: bool,
+	writers: Mutex<HashMap<String, FeatureCollectionWriter>>,
+}
+
+impl FeatureCollectionSink {
+	pub fn new(dir: &Path, gzip: bool, per_level: bool) -> Self {
+		Self { dir: dir.to_path_buf(), gzip, per_level, writers: Mutex::new(HashMap::new()) }
+	}
+}
+
+impl FeatureSink for FeatureCollectionSink {
+	fn write(&self, feature: &Feature) -> anyhow::Result<()> {
+		let key = if self.per_level { level_key(feature) } else { "features".into() };
+		let serialized = serde_json::to_vec(feature)?;
+
+		let mut writers = self.writers.lock();
+		if !writers.contains_key(&key) {
+			let writer = FeatureCollectionWriter::create(&self.dir.join(format!("{key}.geojson")), self.gzip)?;
+			writers.insert(key.clone(), writer);
+		}
+		writers.get_mut(&key).unwrap().write_serialized(&serialized)
+	}
+
+	fn finish(&self) -> anyhow::Result<()> {
+		for (key, writer) in self.writers.lock().drain() {
+			let written = writer.finish()?;
+			println!("written {written} features to {key}.geojson");
+		}
+		Ok(())
+	}
+}
+
+/// Newline delimited GeoJSON, one feature per line.
+/// With `record_separator` every line is prefixed with RS (0x1E) as GeoJSON Text Sequences require (RFC 8142)
+pub struct FeatureSequenceSink {
+	record_separator: bool,
+	out: Mutex<Option<Output>>,
+}
+
+impl FeatureSequenceSink {
+	pub fn create(dir: &Path, gzip: bool, record_separator: bool) -> anyhow::Result<Self> {
+		let file_name = if record_separator { "features.geojsons" } else { "features.ndjson" };
+		Ok(Self { record_separator, out: Mutex::new(Some(Output::create(&dir.join(file_name), gzip)?)) })
+	}
+}
+
+impl FeatureSink for FeatureSequenceSink {
+	fn write(&self, feature: &Feature) -> anyhow::Result<()> {
+		let mut line = Vec::with_capacity(4096);
+		if self.record_separator {
+			line.push(0x1E);
+		}
+		serde_json::to_writer(&mut line, feature)?;
+		line.push(b'\n');
+
+		let mut out = self.out.lock();
+		out.as_mut().ok_or_else(|| anyhow::anyhow!("sink is already finished"))?.write_all(&line)?;
+		Ok(())
+	}
+
+	fn finish(&self) -> anyhow::Result<()> {
+		if let Some(out) = self.out.lock().take() {
+			out.finish()?;
+		}
+		Ok(())
+	}
+}
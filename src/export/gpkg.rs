@@ -0,0 +1,218 @@
+// https://www.geopackage.org/spec130/
+
+use std::{collections::HashMap, path::Path};
+
+use geo::{BoundingRect, Rect};
+use ::geojson::Feature;
+use parking_lot::Mutex;
+use rusqlite::{params, Connection};
+
+use super::{feature_geometry, to_wkb, Attributes, FeatureSink, Tier};
+
+const SRS_ID: i32 = 4326;
+const WGS84_WKT: &str = r#"GEOGCS["WGS 84",DATUM["WGS_1984",SPHEROID["WGS 84",6378137,298.257223563,AUTHORITY["EPSG","7030"]],AUTHORITY["EPSG","6326"]],PRIMEM["Greenwich",0,AUTHORITY["EPSG","8901"]],UNIT["degree",0.0174532925199433,AUTHORITY["EPSG","9122"]],AUTHORITY["EPSG","4326"]]"#;
+
+const SCHEMA: &str = r#"
+	PRAGMA application_id = 1196444487;
+	PRAGMA user_version = 10300;
+
+	CREATE TABLE gpkg_spatial_ref_sys (
+		srs_name TEXT NOT NULL,
+		srs_id INTEGER PRIMARY KEY,
+		organization TEXT NOT NULL,
+		organization_coordsys_id INTEGER NOT NULL,
+		definition TEXT NOT NULL,
+		description TEXT
+	);
+	CREATE TABLE gpkg_contents (
+		table_name TEXT NOT NULL PRIMARY KEY,
+		data_type TEXT NOT NULL,
+		identifier TEXT UNIQUE,
+		description TEXT DEFAULT '',
+		last_change DATETIME NOT NULL DEFAULT (strftime('%Y-%m-%dT%H:%M:%fZ','now')),
+		min_x DOUBLE,
+		min_y DOUBLE,
+		max_x DOUBLE,
+		max_y DOUBLE,
+		srs_id INTEGER,
+		CONSTRAINT fk_gc_r_srs_id FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys(srs_id)
+	);
+	CREATE TABLE gpkg_geometry_columns (
+		table_name TEXT NOT NULL,
+		column_name TEXT NOT NULL,
+		geometry_type_name TEXT NOT NULL,
+		srs_id INTEGER NOT NULL,
+		z TINYINT NOT NULL,
+		m TINYINT NOT NULL,
+		CONSTRAINT pk_geom_cols PRIMARY KEY (table_name, column_name),
+		CONSTRAINT uk_gc_table_name UNIQUE (table_name),
+		CONSTRAINT fk_gc_tn FOREIGN KEY (table_name) REFERENCES gpkg_contents(table_name),
+		CONSTRAINT fk_gc_srs FOREIGN KEY (srs_id) REFERENCES gpkg_spatial_ref_sys (srs_id)
+	);
+	CREATE TABLE gpkg_extensions (
+		table_name TEXT,
+		column_name TEXT,
+		extension_name TEXT NOT NULL,
+		definition TEXT NOT NULL,
+		scope TEXT NOT NULL,
+		CONSTRAINT ge_tce UNIQUE (table_name, column_name, extension_name)
+	);
+
+	INSERT INTO gpkg_spatial_ref_sys VALUES ('Undefined cartesian SRS', -1, 'NONE', -1, 'undefined', 'undefined cartesian coordinate reference system');
+	INSERT INTO gpkg_spatial_ref_sys VALUES ('Undefined geographic SRS', 0, 'NONE', 0, 'undefined', 'undefined geographic coordinate reference system');
+"#;
+
+const LAYER_SCHEMA: &str = r#"
+	CREATE TABLE "{t}" (
+		fid INTEGER PRIMARY KEY AUTOINCREMENT NOT NULL,
+		geom GEOMETRY,
+		osm_id TEXT NOT NULL,
+		name TEXT,
+		name_en TEXT,
+		name_ru TEXT,
+		iso_code TEXT,
+		wikidata TEXT,
+		admin_level INTEGER,
+		population INTEGER,
+		parent_ids TEXT,
+		osm_url TEXT
+	);
+	INSERT INTO gpkg_contents (table_name, data_type, identifier, srs_id) VALUES ('{t}', 'features', '{t}', 4326);
+	INSERT INTO gpkg_geometry_columns VALUES ('{t}', 'geom', 'GEOMETRY', 4326, 0, 0);
+	CREATE VIRTUAL TABLE "rtree_{t}_geom" USING rtree(id, minx, maxx, miny, maxy);
+	INSERT INTO gpkg_extensions VALUES ('{t}', 'geom', 'gpkg_rtree_index', 'http://www.geopackage.org/spec120/#extension_rtree', 'write-only');
+"#;
+
+// created after data is written, ST_* functions are only provided by GeoPackage aware clients
+const RTREE_TRIGGERS: &str = r#"
+	CREATE TRIGGER "rtree_{t}_geom_insert" AFTER INSERT ON "{t}"
+		WHEN (new.geom NOT NULL AND NOT ST_IsEmpty(NEW.geom))
+	BEGIN
+		INSERT OR REPLACE INTO "rtree_{t}_geom" VALUES (NEW.fid, ST_MinX(NEW.geom), ST_MaxX(NEW.geom), ST_MinY(NEW.geom), ST_MaxY(NEW.geom));
+	END;
+	CREATE TRIGGER "rtree_{t}_geom_update1" AFTER UPDATE OF geom ON "{t}"
+		WHEN OLD.fid = NEW.fid AND (NEW.geom NOTNULL AND NOT ST_IsEmpty(NEW.geom))
+	BEGIN
+		INSERT OR REPLACE INTO "rtree_{t}_geom" VALUES (NEW.fid, ST_MinX(NEW.geom), ST_MaxX(NEW.geom), ST_MinY(NEW.geom), ST_MaxY(NEW.geom));
+	END;
+	CREATE TRIGGER "rtree_{t}_geom_update2" AFTER UPDATE OF geom ON "{t}"
+		WHEN OLD.fid = NEW.fid AND (NEW.geom ISNULL OR ST_IsEmpty(NEW.geom))
+	BEGIN
+		DELETE FROM "rtree_{t}_geom" WHERE id = OLD.fid;
+	END;
+	CREATE TRIGGER "rtree_{t}_geom_update3" AFTER UPDATE ON "{t}"
+		WHEN OLD.fid != NEW.fid AND (NEW.geom NOTNULL AND NOT ST_IsEmpty(NEW.geom))
+	BEGIN
+		DELETE FROM "rtree_{t}_geom" WHERE id = OLD.fid;
+		INSERT OR REPLACE INTO "rtree_{t}_geom" VALUES (NEW.fid, ST_MinX(NEW.geom), ST_MaxX(NEW.geom), ST_MinY(NEW.geom), ST_MaxY(NEW.geom));
+	END;
+	CREATE TRIGGER "rtree_{t}_geom_update4" AFTER UPDATE ON "{t}"
+		WHEN OLD.fid != NEW.fid AND (NEW.geom ISNULL OR ST_IsEmpty(NEW.geom))
+	BEGIN
+		DELETE FROM "rtree_{t}_geom" WHERE id IN (OLD.fid, NEW.fid);
+	END;
+	CREATE TRIGGER "rtree_{t}_geom_delete" AFTER DELETE ON "{t}"
+		WHEN old.geom NOT NULL
+	BEGIN
+		DELETE FROM "rtree_{t}_geom" WHERE id = OLD.fid;
+	END;
+"#;
+
+/// Single GeoPackage with `countries`, `regions`, `districts` and `cities` layers and their rtree spatial indexes.
+/// Everything is written in one transaction committed on `finish`
+pub struct GeoPackageSink {
+	conn: Mutex<Connection>,
+	extents: Mutex<HashMap<Tier, Rect>>,
+}
+
+impl GeoPackageSink {
+	pub fn create(path: &Path) -> anyhow::Result<Self> {
+		if path.exists() {
+			std::fs::remove_file(path)?;
+		}
+		let conn = Connection::open(path)?;
+		conn.execute_batch("PRAGMA journal_mode = OFF; PRAGMA synchronous = OFF;")?;
+		conn.execute_batch(SCHEMA)?;
+		conn.execute(
+			"INSERT INTO gpkg_spatial_ref_sys VALUES ('WGS 84 geodetic', ?1, 'EPSG', ?1, ?2, 'longitude/latitude coordinates in decimal degrees on the WGS 84 spheroid')",
+			params![SRS_ID, WGS84_WKT],
+		)?;
+		for tier in Tier::ALL {
+			conn.execute_batch(&LAYER_SCHEMA.replace("{t}", tier.layer_name()))?;
+		}
+		conn.execute_batch("BEGIN")?;
+
+		Ok(Self { conn: Mutex::new(conn), extents: Mutex::new(HashMap::new()) })
+	}
+}
+
+/// GeoPackageBinary header (little endian, xy envelope) followed by WKB
+fn gpkg_geometry(geometry: &geo::Geometry, rect: &Rect) -> Vec<u8> {
+	let mut blob = Vec::with_capacity(64);
+	blob.extend_from_slice(b"GP");
+	blob.push(0);
+	blob.push(0b0000_0011);
+	blob.extend_from_slice(&SRS_ID.to_le_bytes());
+	for v in [rect.min().x, rect.max().x, rect.min().y, rect.max().y] {
+		blob.extend_from_slice(&v.to_le_bytes());
+	}
+	blob.extend_from_slice(&to_wkb(geometry));
+	blob
+}
+
+impl FeatureSink for GeoPackageSink {
+	fn write(&self, feature: &Feature) -> anyhow::Result<()> {
+		let Some(tier) = Tier::of(feature) else { return Ok(()) };
+		let Some(geometry) = feature_geometry(feature) else { return Ok(()) };
+		let Some(rect) = geometry.bounding_rect() else { return Ok(()) };
+		let attributes = Attributes::from_feature(feature);
+		let blob = gpkg_geometry(&geometry, &rect);
+		let table = tier.layer_name();
+
+		{
+			let conn = self.conn.lock();
+			conn.prepare_cached(&format!(
+				r#"INSERT INTO "{table}" (geom, osm_id, name, name_en, name_ru, iso_code, wikidata, admin_level, population, parent_ids, osm_url) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#
+			))?.execute(params![
+				blob,
+				attributes.osm_id,
+				attributes.name,
+				attributes.name_en,
+				attributes.name_ru,
+				attributes.iso_code,
+				attributes.wikidata,
+				attributes.admin_level,
+				attributes.population,
+				(!attributes.parents.is_empty()).then(|| attributes.parents.join(",")),
+				attributes.osm_url,
+			])?;
+			let fid = conn.last_insert_rowid();
+			conn.prepare_cached(&format!(r#"INSERT INTO "rtree_{table}_geom" VALUES (?, ?, ?, ?, ?)"#))?
+				.execute(params![fid, rect.min().x, rect.max().x, rect.min().y, rect.max().y])?;
+		}
+
+		let mut extents = self.extents.lock();
+		let extent = extents.entry(tier).or_insert(rect);
+		*extent = Rect::new(
+			geo::Coord { x: extent.min().x.min(rect.min().x), y: extent.min().y.min(rect.min().y) },
+			geo::Coord { x: extent.max().x.max(rect.max().x), y: extent.max().y.max(rect.max().y) },
+		);
+		Ok(())
+	}
+
+	fn finish(&self) -> anyhow::Result<()> {
+		let conn = self.conn.lock();
+		for (tier, extent) in self.extents.lock().iter() {
+			conn.execute(
+				"UPDATE gpkg_contents SET min_x = ?, min_y = ?, max_x = ?, max_y = ? WHERE table_name = ?",
+				params![extent.min().x, extent.min().y, extent.max().x, extent.max().y, tier.layer_name()],
+			)?;
+		}
+		for tier in Tier::ALL {
+			conn.execute_batch(&RTREE_TRIGGERS.replace("{t}", tier.layer_name()))?;
+		}
+		conn.execute_batch("COMMIT")?;
+		println!("written geopackage layers: {}", Tier::ALL.map(|t| t.layer_name()).join(", "));
+		Ok(())
+	}
+}
@@ -0,0 +1,296 @@
+mod csv;
+mod fgb;
+mod geojson;
+mod gpkg;
+mod mvt;
+mod parquet;
+mod pmtiles;
+mod poly;
+mod postgis;
+mod shp;
+mod tree;
+mod wkb;
+
+use std::{
+	collections::HashMap,
+	fs::File,
+	io::{BufWriter, Write},
+	path::{Path, PathBuf},
+};
+
+use flate2::{write::GzEncoder, Compression};
+use ::geojson::Feature;
+
+pub use self::{
+	csv::{table_specs_from_env, write_table},
+	fgb::FlatGeobufSink,
+	geojson::{FeatureCollectionSink, FeatureSequenceSink, GeoJsonFilesSink},
+	gpkg::GeoPackageSink,
+	parquet::{write_hierarchy as write_hierarchy_parquet, GeoParquetSink},
+	pmtiles::PmTilesSink,
+	poly::PolySink,
+	postgis::PostgisSink,
+	shp::ShapefileSink,
+	tree::GeoJsonTreeSink,
+	wkb::to_wkb,
+};
+
+/// Receives features one by one, possibly from several threads at once.
+/// `finish` is called once after the last feature and must flush everything to disk
+pub trait FeatureSink: Send + Sync {
+	fn write(&self, feature: &Feature) -> anyhow::Result<()>;
+	fn finish(&self) -> anyhow::Result<()>;
+}
+
+/// Buffered file output, optionally gzipped
+pub enum Output {
+	Plain(BufWriter<File>),
+	Gzip(GzEncoder<BufWriter<File>>),
+}
+
+impl Output {
+	/// Creates `path`, appending `.gz` to it when `gzip` is set
+	pub fn create(path: &Path, gzip: bool) -> anyhow::Result<Self> {
+		if !gzip {
+			return Ok(Output::Plain(BufWriter::new(File::create(path)?)));
+		}
+		let mut path = path.as_os_str().to_owned();
+		path.push(".gz");
+		Ok(Output::Gzip(GzEncoder::new(BufWriter::new(File::create(PathBuf::from(path))?), Compression::default())))
+	}
+
+	pub fn finish(self) -> anyhow::Result<()> {
+		match self {
+			Output::Plain(mut w) => w.flush()?,
+			Output::Gzip(w) => w.finish()?.flush()?,
+		};
+		Ok(())
+	}
+}
+
+impl Write for Output {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		match self {
+			Output::Plain(w) => w.write(buf),
+			Output::Gzip(w) => w.write(buf),
+		}
+	}
+
+	fn flush(&mut self) -> std::io::Result<()> {
+		match self {
+			Output::Plain(w) => w.flush(),
+			Output::Gzip(w) => w.flush(),
+		}
+	}
+}
+
+/// Turns feature id like `relation/123/Name` into something usable as a file name
+pub fn safe_file_name(id: &str) -> String {
+	let mut safe_name = id.to_string();
+	safe_name.retain(|c| !r#"\\&:<>|*"#.contains(c));
+	safe_name.replace("/", "_")
+}
+
+/// Feature geometry converted back to `geo`
+pub fn feature_geometry(feature: &Feature) -> Option<geo::Geometry> {
+	geo::Geometry::try_from(&feature.geometry.as_ref()?.value).ok()
+}
+
+/// Property value for formats with plain string attributes, non-string values are written as json
+pub fn property_string(value: &serde_json::Value) -> String {
+	match value {
+		serde_json::Value::String(s) => s.clone(),
+		other => other.to_string(),
+	}
+}
+
+/// Hierarchy tier for formats with a layer per level
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Tier {
+	Country,
+	Region,
+	District,
+	City,
+}
+
+impl Tier {
+	pub const ALL: [Tier; 4] = [Tier::Country, Tier::Region, Tier::District, Tier::City];
+
+	pub fn layer_name(&self) -> &'static str {
+		match self {
+			Tier::Country => "countries",
+			Tier::Region => "regions",
+			Tier::District => "districts",
+			Tier::City => "cities",
+		}
+	}
+
+	/// `mapped_type` of pipeline1 places wins, otherwise it's decided by admin_level or place tag
+	pub fn of(feature: &Feature) -> Option<Tier> {
+		let property = |key: &str| feature.property(key).and_then(|v| v.as_str());
+
+		match property("mapped_type") {
+			Some("country") => return Some(Tier::Country),
+			Some("region") => return Some(Tier::Region),
+			Some("city") => return Some(Tier::City),
+			_ => {},
+		}
+		if let Some(al) = property("admin_level").and_then(|al| al.parse::<u8>().ok()) {
+			return match al {
+				2 => Some(Tier::Country),
+				3 | 4 => Some(Tier::Region),
+				5..=7 => Some(Tier::District),
+				8 | 9 => Some(Tier::City),
+				// suburbs and neighbourhoods
+				_ => None,
+			};
+		}
+		match property("place")? {
+			"country" => Some(Tier::Country),
+			"state" | "region" | "province" => Some(Tier::Region),
+			"county" | "district" | "municipality" => Some(Tier::District),
+			"city" | "town" | "village" => Some(Tier::City),
+			_ => None,
+		}
+	}
+}
+
+/// Fixed attribute set for formats with typed columns
+pub struct Attributes {
+	/// `relation/123`
+	pub osm_id: String,
+	pub osm_url: Option<String>,
+	pub name: Option<String>,
+	pub name_en: Option<String>,
+	pub name_ru: Option<String>,
+	/// ISO3166-1 for countries, ISO3166-2 for subdivisions
+	pub iso_code: Option<String>,
+	pub wikidata: Option<String>,
+	pub admin_level: Option<u8>,
+	pub population: Option<i64>,
+	/// osm ids of hierarchy parents, only pipeline1 places have them
+	pub parents: Vec<String>,
+}
+
+impl Attributes {
+	pub fn from_feature(feature: &Feature) -> Self {
+		let property = |key: &str| feature.property(key).and_then(|v| v.as_str()).map(|v| v.to_string());
+		let osm_id = match feature.id {
+			Some(::geojson::feature::Id::String(ref id)) => id.splitn(3, '/').take(2).collect::<Vec<_>>().join("/"),
+			Some(::geojson::feature::Id::Number(ref n)) => n.to_string(),
+			None => String::new(),
+		};
+
+		Self {
+			osm_id,
+			osm_url: property("osm_url"),
+			name: property("name"),
+			name_en: property("name:en"),
+			name_ru: property("name:ru"),
+			iso_code: property("ISO3166-1:alpha2").or_else(|| property("ISO3166-1")).or_else(|| property("ISO3166-2")),
+			wikidata: property("wikidata"),
+			admin_level: property("admin_level").and_then(|al| al.parse().ok()),
+			population: property("population").as_deref().and_then(parse_population),
+			parents: feature.property("parents")
+				.and_then(|p| p.as_array())
+				.map(|p| p.iter().filter_map(|id| id.as_str().map(|id| id.to_string())).collect())
+				.unwrap_or_default(),
+		}
+	}
+}
+
+/// `population` tag as a number, thousands separators are ignored
+pub fn parse_population(population: &str) -> Option<i64> {
+	population.replace([' ', ','], "").parse().ok()
+}
+
+/// Place of one tier in a hierarchy row
+pub struct HierarchyItem {
+	/// `relation/123`
+	pub osm_id: String,
+	/// ISO3166-1 for countries, ISO3166-2 for regions
+	pub iso_code: Option<String>,
+	pub osm_ref: Option<String>,
+	pub wikidata: Option<String>,
+	pub tags: HashMap<String, String>,
+}
+
+impl HierarchyItem {
+	/// `name:<lang>` tag, plain `name` is used instead of a missing one when `fallback` is set
+	pub fn name(&self, lang: &str, fallback: bool) -> Option<&String> {
+		self.tags.get(&format!("name:{lang}")).or_else(|| fallback.then(|| self.tags.get("name")).flatten())
+	}
+}
+
+/// Flattened hierarchy of a pipeline1 place, a row of `table.csv`
+#[derive(Default)]
+pub struct HierarchyRow {
+	pub country: Option<HierarchyItem>,
+	pub region: Option<HierarchyItem>,
+	pub city: Option<HierarchyItem>,
+	pub admin_level: Option<u8>,
+	pub lon: f64,
+	pub lat: f64,
+	/// tags of the place itself
+	pub tags: HashMap<String, String>,
+	/// osm ids of direct parents
+	pub parent_ids: Vec<String>,
+}
+
+impl HierarchyRow {
+	pub fn item(&self, tier: Tier) -> Option<&HierarchyItem> {
+		match tier {
+			Tier::Country => self.country.as_ref(),
+			Tier::Region => self.region.as_ref(),
+			Tier::District => None,
+			Tier::City => self.city.as_ref(),
+		}
+	}
+}
+
+/// Key to split outputs by: `al<admin_level>`, `place_<place>` or `other`
+pub fn level_key(feature: &Feature) -> String {
+	if let Some(al) = feature.property("admin_level").and_then(|al| al.as_str()) {
+		return format!("al{al}");
+	}
+	if let Some(place) = feature.property("place").and_then(|pl| pl.as_str()) {
+		return format!("place_{place}");
+	}
+	"other".into()
+}
+
+/// Formats from comma separated `OUTPUT_FORMATS` env variable, `default` is used when it's not set
+pub fn formats_from_env(default: &[&str]) -> Vec<String> {
+	std::env::var("OUTPUT_FORMATS")
+		.map(|f| f.split(',').map(|f| f.trim().to_string()).filter(|f| !f.is_empty()).collect::<Vec<_>>())
+		.unwrap_or_else(|_| default.iter().map(|f| f.to_string()).collect())
+}
+
+/// Builds sinks for `formats_from_env`.
+/// Every sink writes into `dir`, `OUTPUT_GZIP=1` compresses outputs that support it
+pub fn sinks_from_env(dir: &Path, default: &[&str]) -> anyhow::Result<Vec<Box<dyn FeatureSink>>> {
+	let formats = formats_from_env(default);
+	let gzip = std::env::var("OUTPUT_GZIP").is_ok_and(|v| v == "1");
+
+	let mut sinks = Vec::<Box<dyn FeatureSink>>::with_capacity(formats.len());
+	for format in formats {
+		let sink: Box<dyn FeatureSink> = match format.as_str() {
+			"geojson_files" => Box::new(GeoJsonFilesSink::new(dir)),
+			"geojson_tree" => Box::new(GeoJsonTreeSink::new(dir)),
+			"geojson" => Box::new(FeatureCollectionSink::new(dir, gzip, false)),
+			"geojson_per_level" => Box::new(FeatureCollectionSink::new(dir, gzip, true)),
+			"ndjson" => Box::new(FeatureSequenceSink::create(dir, gzip, false)?),
+			"geojsonseq" => Box::new(FeatureSequenceSink::create(dir, gzip, true)?),
+			"fgb" => Box::new(FlatGeobufSink::new(dir)),
+			"gpkg" => Box::new(GeoPackageSink::create(&dir.join("places.gpkg"))?),
+			"geoparquet" => Box::new(GeoParquetSink::create(&dir.join("features.parquet"))?),
+			"pmtiles" => Box::new(PmTilesSink::new(&dir.join("tiles.pmtiles"))?),
+			"poly" => Box::new(PolySink::new(dir)),
+			"postgis" => Box::new(PostgisSink::new(dir, gzip)),
+			"shp" => Box::new(ShapefileSink::new(dir)),
+			_ => anyhow::bail!("unknown output format {format}"),
+		};
+		sinks.push(sink);
+	}
+	Ok(sinks)
+}
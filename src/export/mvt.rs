@@ -0,0 +1,301 @@
+// https://github.com/mapbox/vector-tile-spec/tree/master/2.1
+
+use std::collections::HashMap;
+
+use geo::{BoundingRect, Coord, LineString, MultiPolygon, Polygon, Rect, Simplify};
+use serde::{Deserialize, Serialize};
+
+pub const EXTENT: u32 = 4096;
+/// Tile buffer in tile units, geometries are clipped this far outside of the tile
+const BUFFER: f64 = 64.0;
+const MAX_LAT: f64 = 85.051_128_779_806_59;
+
+const POINT: u32 = 1;
+const POLYGON: u32 = 3;
+
+const CMD_MOVE_TO: u32 = 1;
+const CMD_LINE_TO: u32 = 2;
+const CMD_CLOSE_PATH: u32 = 7;
+
+/// Geometry in web mercator world coordinates, `0..1` on both axes with y pointing down
+#[derive(Clone)]
+pub enum WorldGeometry {
+	Point(Coord),
+	Polygons(MultiPolygon),
+}
+
+impl WorldGeometry {
+	pub fn from_geo(geometry: &geo::Geometry) -> Option<Self> {
+		let project = |c: &Coord| {
+			let lat = c.y.clamp(-MAX_LAT, MAX_LAT).to_radians();
+			Coord {
+				x: (c.x + 180.0) / 360.0,
+				y: (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / std::f64::consts::PI) / 2.0,
+			}
+		};
+		let project_ring = |ring: &LineString| LineString(ring.0.iter().map(project).collect());
+		let project_polygon = |p: &Polygon| Polygon::new(project_ring(p.exterior()), p.interiors().iter().map(project_ring).collect());
+
+		match geometry {
+			geo::Geometry::Point(p) => Some(WorldGeometry::Point(project(&p.0))),
+			geo::Geometry::Polygon(p) => Some(WorldGeometry::Polygons(MultiPolygon(vec![project_polygon(p)]))),
+			geo::Geometry::MultiPolygon(mp) => Some(WorldGeometry::Polygons(MultiPolygon(mp.0.iter().map(project_polygon).collect()))),
+			_ => None,
+		}
+	}
+
+	pub fn bounding_rect(&self) -> Option<Rect> {
+		match self {
+			WorldGeometry::Point(c) => Some(Rect::new(*c, *c)),
+			WorldGeometry::Polygons(mp) => mp.bounding_rect(),
+		}
+	}
+
+	/// Geometry as seen at `zoom`: simplified by `tolerance` pixels of a 256px tile,
+	/// polygons and holes smaller than `min_size` pixels are dropped
+	pub fn generalize(&self, zoom: u8, tolerance: f64, min_size: f64) -> Option<Self> {
+		let pixel = 1.0 / (256.0 * (1u64 << zoom) as f64);
+		let big_enough = |ring: &LineString| ring.bounding_rect()
+			.is_some_and(|r| r.width().max(r.height()) >= min_size * pixel);
+
+		match self {
+			WorldGeometry::Point(_) => Some(self.clone()),
+			WorldGeometry::Polygons(mp) => {
+				let polygons = mp.simplify(&(tolerance * pixel)).0.into_iter()
+					.filter(|p| big_enough(p.exterior()))
+					.map(|p| {
+						let (exterior, interiors) = p.into_inner();
+						Polygon::new(exterior, interiors.into_iter().filter(|r| big_enough(r)).collect())
+					})
+					.collect::<Vec<_>>();
+				(!polygons.is_empty()).then_some(WorldGeometry::Polygons(MultiPolygon(polygons)))
+			},
+		}
+	}
+}
+
+/// Tiles of `zoom` touched by `rect` including the tile buffer
+pub fn tile_range(rect: &Rect, zoom: u8) -> impl Iterator<Item = (u32, u32)> {
+	let n = (1u64 << zoom) as f64;
+	let buffer = BUFFER / EXTENT as f64;
+	let max = (1u32 << zoom) - 1;
+	let to_tile = move |v: f64| ((v * n).floor().max(0.0) as u32).min(max);
+	let (min_x, max_x) = (to_tile(rect.min().x - buffer / n), to_tile(rect.max().x + buffer / n));
+	let (min_y, max_y) = (to_tile(rect.min().y - buffer / n), to_tile(rect.max().y + buffer / n));
+	(min_x..=max_x).flat_map(move |x| (min_y..=max_y).map(move |y| (x, y)))
+}
+
+/// Sutherland-Hodgman against a single half plane, `ring` is open (no repeated first point)
+fn clip_half(ring: &[Coord], inside: impl Fn(&Coord) -> bool, intersect: impl Fn(&Coord, &Coord) -> Coord) -> Vec<Coord> {
+	let mut out = Vec::with_capacity(ring.len());
+	let Some(mut prev) = ring.last() else { return out };
+	for cur in ring {
+		match (inside(prev), inside(cur)) {
+			(true, true) => out.push(*cur),
+			(true, false) => out.push(intersect(prev, cur)),
+			(false, true) => {
+				out.push(intersect(prev, cur));
+				out.push(*cur);
+			},
+			(false, false) => {},
+		}
+		prev = cur;
+	}
+	out
+}
+
+fn clip_ring(ring: &[Coord], min: Coord, max: Coord) -> Vec<Coord> {
+	let at_x = |x: f64| move |a: &Coord, b: &Coord| Coord { x, y: a.y + (b.y - a.y) * (x - a.x) / (b.x - a.x) };
+	let at_y = |y: f64| move |a: &Coord, b: &Coord| Coord { x: a.x + (b.x - a.x) * (y - a.y) / (b.y - a.y), y };
+
+	let ring = clip_half(ring, |c| c.x >= min.x, at_x(min.x));
+	let ring = clip_half(&ring, |c| c.x <= max.x, at_x(max.x));
+	let ring = clip_half(&ring, |c| c.y >= min.y, at_y(min.y));
+	clip_half(&ring, |c| c.y <= max.y, at_y(max.y))
+}
+
+fn zigzag(v: i64) -> u32 {
+	((v << 1) ^ (v >> 63)) as u32
+}
+
+fn command(id: u32, count: usize) -> u32 {
+	(id & 0x7) | ((count as u32) << 3)
+}
+
+/// Geometry commands with the cursor shared by all parts of a feature
+struct Encoder {
+	commands: Vec<u32>,
+	cursor: (i64, i64),
+}
+
+impl Encoder {
+	fn push_point(&mut self, (x, y): (i64, i64)) {
+		self.commands.push(zigzag(x - self.cursor.0));
+		self.commands.push(zigzag(y - self.cursor.1));
+		self.cursor = (x, y);
+	}
+
+	fn push_ring(&mut self, ring: &[(i64, i64)]) {
+		self.commands.push(command(CMD_MOVE_TO, 1));
+		self.push_point(ring[0]);
+		self.commands.push(command(CMD_LINE_TO, ring.len() - 1));
+		ring[1..].iter().for_each(|p| self.push_point(*p));
+		self.commands.push(command(CMD_CLOSE_PATH, 1));
+	}
+}
+
+/// Twice the signed area, positive for exterior rings by the tile spec (y axis points down)
+fn ring_area(ring: &[(i64, i64)]) -> i64 {
+	let mut area = 0;
+	for (i, a) in ring.iter().enumerate() {
+		let b = ring[(i + 1) % ring.len()];
+		area += a.0 * b.1 - b.0 * a.1;
+	}
+	area
+}
+
+/// Geometry type and commands of `geometry` clipped to tile `x`/`y` of `zoom`, `None` if nothing is left
+pub fn tile_geometry(geometry: &WorldGeometry, zoom: u8, x: u32, y: u32) -> Option<(u32, Vec<u32>)> {
+	let n = (1u64 << zoom) as f64;
+	let to_tile = |c: &Coord| (((c.x * n - x as f64) * EXTENT as f64).round() as i64, ((c.y * n - y as f64) * EXTENT as f64).round() as i64);
+	let mut encoder = Encoder { commands: Vec::new(), cursor: (0, 0) };
+
+	match geometry {
+		WorldGeometry::Point(c) => {
+			let (px, py) = to_tile(c);
+			if px < 0 || py < 0 || px >= EXTENT as i64 || py >= EXTENT as i64 {
+				return None;
+			}
+			encoder.commands.push(command(CMD_MOVE_TO, 1));
+			encoder.push_point((px, py));
+			Some((POINT, encoder.commands))
+		},
+		WorldGeometry::Polygons(mp) => {
+			let buffer = BUFFER / EXTENT as f64;
+			let min = Coord { x: (x as f64 - buffer) / n, y: (y as f64 - buffer) / n };
+			let max = Coord { x: (x as f64 + 1.0 + buffer) / n, y: (y as f64 + 1.0 + buffer) / n };
+			let tile = Rect::new(min, max);
+
+			let prepare = |ring: &LineString, exterior: bool| -> Option<Vec<(i64, i64)>> {
+				let open = &ring.0[..ring.0.len().saturating_sub(1)];
+				let mut points = clip_ring(open, min, max).iter().map(to_tile).collect::<Vec<_>>();
+				points.dedup();
+				while points.len() > 1 && points.first() == points.last() {
+					points.pop();
+				}
+				if points.len() < 3 {
+					return None;
+				}
+				let area = ring_area(&points);
+				if area == 0 {
+					return None;
+				}
+				if (area > 0) != exterior {
+					points.reverse();
+				}
+				Some(points)
+			};
+
+			for polygon in mp.0.iter() {
+				let Some(rect) = polygon.exterior().bounding_rect() else { continue };
+				if rect.max().x < tile.min().x || rect.min().x > tile.max().x || rect.max().y < tile.min().y || rect.min().y > tile.max().y {
+					continue;
+				}
+				let Some(exterior) = prepare(polygon.exterior(), true) else { continue };
+				encoder.push_ring(&exterior);
+				for interior in polygon.interiors().iter().filter_map(|r| prepare(r, false)) {
+					encoder.push_ring(&interior);
+				}
+			}
+			(!encoder.commands.is_empty()).then_some((POLYGON, encoder.commands))
+		},
+	}
+}
+
+#[derive(Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Value {
+	String(String),
+	Int(i64),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TileFeature {
+	pub geom_type: u32,
+	pub geometry: Vec<u32>,
+	pub properties: Vec<(String, Value)>,
+}
+
+fn write_varint(out: &mut Vec<u8>, mut v: u64) {
+	while v >= 0x80 {
+		out.push((v as u8) | 0x80);
+		v >>= 7;
+	}
+	out.push(v as u8);
+}
+
+fn write_key(out: &mut Vec<u8>, field: u32, wire_type: u32) {
+	write_varint(out, ((field << 3) | wire_type) as u64);
+}
+
+fn write_bytes(out: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+	write_key(out, field, 2);
+	write_varint(out, bytes.len() as u64);
+	out.extend_from_slice(bytes);
+}
+
+fn write_packed(out: &mut Vec<u8>, field: u32, values: &[u32]) {
+	let mut packed = Vec::with_capacity(values.len() * 2);
+	values.iter().for_each(|v| write_varint(&mut packed, *v as u64));
+	write_bytes(out, field, &packed);
+}
+
+fn encode_layer(name: &str, features: &[&TileFeature]) -> Vec<u8> {
+	let mut keys = Vec::<&str>::new();
+	let mut key_ids = HashMap::<&str, u32>::new();
+	let mut values = Vec::<&Value>::new();
+	let mut value_ids = HashMap::<&Value, u32>::new();
+
+	let mut layer = Vec::new();
+	write_key(&mut layer, 15, 0);
+	write_varint(&mut layer, 2);
+	write_bytes(&mut layer, 1, name.as_bytes());
+
+	for feature in features {
+		let mut tags = Vec::with_capacity(feature.properties.len() * 2);
+		for (key, value) in feature.properties.iter() {
+			tags.push(*key_ids.entry(key.as_str()).or_insert_with(|| { keys.push(key.as_str()); keys.len() as u32 - 1 }));
+			tags.push(*value_ids.entry(value).or_insert_with(|| { values.push(value); values.len() as u32 - 1 }));
+		}
+		let mut encoded = Vec::new();
+		write_packed(&mut encoded, 2, &tags);
+		write_key(&mut encoded, 3, 0);
+		write_varint(&mut encoded, feature.geom_type as u64);
+		write_packed(&mut encoded, 4, &feature.geometry);
+		write_bytes(&mut layer, 2, &encoded);
+	}
+
+	keys.iter().for_each(|key| write_bytes(&mut layer, 3, key.as_bytes()));
+	for value in values {
+		let mut encoded = Vec::new();
+		match value {
+			Value::String(s) => write_bytes(&mut encoded, 1, s.as_bytes()),
+			Value::Int(i) => {
+				write_key(&mut encoded, 4, 0);
+				write_varint(&mut encoded, *i as u64);
+			},
+		}
+		write_bytes(&mut layer, 4, &encoded);
+	}
+	write_key(&mut layer, 5, 0);
+	write_varint(&mut layer, EXTENT as u64);
+	layer
+}
+
+/// Encodes a tile from `(layer name, features)` pairs, empty layers are skipped
+pub fn encode_tile(layers: &[(&str, Vec<&TileFeature>)]) -> Vec<u8> {
+	let mut tile = Vec::new();
+	for (name, features) in layers.iter().filter(|(_, f)| !f.is_empty()) {
+		write_bytes(&mut tile, 3, &encode_layer(name, features));
+	}
+	tile
+}
@@ -0,0 +1,278 @@
+// https://geoparquet.org/releases/v1.1.0/
+
+use std::{collections::BTreeSet, fs::File, path::Path, sync::Arc};
+
+use arrow_array::{
+	builder::{ListBuilder, StringBuilder},
+	ArrayRef, BinaryArray, Float64Array, Int64Array, RecordBatch, StringArray, UInt8Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use geo::{BoundingRect, Geometry, Rect};
+use ::geojson::Feature;
+use parking_lot::Mutex;
+use parquet::{
+	arrow::ArrowWriter,
+	basic::Compression,
+	file::{metadata::KeyValue, properties::WriterProperties},
+};
+
+use super::{csv::TableSpec, feature_geometry, parse_population, to_wkb, Attributes, FeatureSink, HierarchyItem, HierarchyRow, Tier};
+
+const BATCH_SIZE: usize = 8192;
+
+/// Geometry column stats needed for the `geo` file metadata
+#[derive(Default)]
+struct GeometryStats {
+	types: BTreeSet<&'static str>,
+	bbox: Option<Rect>,
+}
+
+impl GeometryStats {
+	fn add(&mut self, geometry: &Geometry, rect: Rect) {
+		self.types.insert(match geometry {
+			Geometry::Point(_) => "Point",
+			Geometry::Line(_) | Geometry::LineString(_) => "LineString",
+			Geometry::Polygon(_) | Geometry::Rect(_) | Geometry::Triangle(_) => "Polygon",
+			Geometry::MultiPoint(_) => "MultiPoint",
+			Geometry::MultiLineString(_) => "MultiLineString",
+			Geometry::MultiPolygon(_) => "MultiPolygon",
+			Geometry::GeometryCollection(_) => "GeometryCollection",
+		});
+		self.bbox = Some(match self.bbox {
+			Some(bbox) => Rect::new(
+				geo::Coord { x: bbox.min().x.min(rect.min().x), y: bbox.min().y.min(rect.min().y) },
+				geo::Coord { x: bbox.max().x.max(rect.max().x), y: bbox.max().y.max(rect.max().y) },
+			),
+			None => rect,
+		});
+	}
+
+	/// `geo` key value, crs is omitted so readers default to OGC:CRS84
+	fn metadata(&self) -> KeyValue {
+		let mut column = serde_json::json!({
+			"encoding": "WKB",
+			"geometry_types": self.types,
+		});
+		if let Some(bbox) = self.bbox {
+			column["bbox"] = serde_json::json!([bbox.min().x, bbox.min().y, bbox.max().x, bbox.max().y]);
+		}
+		let geo = serde_json::json!({
+			"version": "1.1.0",
+			"primary_column": "geometry",
+			"columns": { "geometry": column },
+		});
+		KeyValue::new("geo".to_string(), geo.to_string())
+	}
+}
+
+fn create_writer(path: &Path, schema: SchemaRef) -> anyhow::Result<ArrowWriter<File>> {
+	let props = WriterProperties::builder().set_compression(Compression::SNAPPY).build();
+	Ok(ArrowWriter::try_new(File::create(path)?, schema, Some(props))?)
+}
+
+fn strings<'a>(values: impl Iterator<Item = &'a Option<String>>) -> ArrayRef {
+	Arc::new(values.map(|v| v.as_deref()).collect::<StringArray>())
+}
+
+struct Writer {
+	writer: Option<ArrowWriter<File>>,
+	rows: Vec<(Attributes, Vec<u8>)>,
+	stats: GeometryStats,
+}
+
+/// GeoParquet file with typed attribute columns and WKB `geometry`, rows are written in batches of 8192
+pub struct GeoParquetSink {
+	schema: SchemaRef,
+	inner: Mutex<Writer>,
+}
+
+impl GeoParquetSink {
+	pub fn create(path: &Path) -> anyhow::Result<Self> {
+		let schema = Arc::new(Schema::new(vec![
+			Field::new("osm_id", DataType::Utf8, false),
+			Field::new("name", DataType::Utf8, true),
+			Field::new("name_en", DataType::Utf8, true),
+			Field::new("name_ru", DataType::Utf8, true),
+			Field::new("iso_code", DataType::Utf8, true),
+			Field::new("wikidata", DataType::Utf8, true),
+			Field::new("admin_level", DataType::UInt8, true),
+			Field::new("population", DataType::Int64, true),
+			Field::new("parents", DataType::List(Arc::new(Field::new("item", DataType::Utf8, true))), false),
+			Field::new("osm_url", DataType::Utf8, true),
+			Field::new("geometry", DataType::Binary, false),
+		]));
+		let writer = create_writer(path, schema.clone())?;
+
+		Ok(Self {
+			schema,
+			inner: Mutex::new(Writer { writer: Some(writer), rows: Vec::with_capacity(BATCH_SIZE), stats: GeometryStats::default() }),
+		})
+	}
+
+	fn write_batch(&self, inner: &mut Writer) -> anyhow::Result<()> {
+		if inner.rows.is_empty() {
+			return Ok(());
+		}
+		let rows = std::mem::take(&mut inner.rows);
+		let mut parents = ListBuilder::new(StringBuilder::new());
+		for (attributes, _) in rows.iter() {
+			parents.append_value(attributes.parents.iter().map(Some));
+		}
+
+		let batch = RecordBatch::try_new(self.schema.clone(), vec![
+			Arc::new(rows.iter().map(|(a, _)| Some(a.osm_id.as_str())).collect::<StringArray>()),
+			strings(rows.iter().map(|(a, _)| &a.name)),
+			strings(rows.iter().map(|(a, _)| &a.name_en)),
+			strings(rows.iter().map(|(a, _)| &a.name_ru)),
+			strings(rows.iter().map(|(a, _)| &a.iso_code)),
+			strings(rows.iter().map(|(a, _)| &a.wikidata)),
+			Arc::new(rows.iter().map(|(a, _)| a.admin_level).collect::<UInt8Array>()),
+			Arc::new(rows.iter().map(|(a, _)| a.population).collect::<Int64Array>()),
+			Arc::new(parents.finish()),
+			strings(rows.iter().map(|(a, _)| &a.osm_url)),
+			Arc::new(rows.iter().map(|(_, wkb)| Some(wkb.as_slice())).collect::<BinaryArray>()),
+		])?;
+		inner.writer.as_mut().expect("parquet writer is already closed").write(&batch)?;
+		Ok(())
+	}
+}
+
+impl FeatureSink for GeoParquetSink {
+	fn write(&self, feature: &Feature) -> anyhow::Result<()> {
+		let Some(geometry) = feature_geometry(feature) else { return Ok(()) };
+		let Some(rect) = geometry.bounding_rect() else { return Ok(()) };
+		let attributes = Attributes::from_feature(feature);
+		let wkb = to_wkb(&geometry);
+
+		let mut inner = self.inner.lock();
+		inner.stats.add(&geometry, rect);
+		inner.rows.push((attributes, wkb));
+		if inner.rows.len() >= BATCH_SIZE {
+			self.write_batch(&mut inner)?;
+		}
+		Ok(())
+	}
+
+	fn finish(&self) -> anyhow::Result<()> {
+		let mut inner = self.inner.lock();
+		self.write_batch(&mut inner)?;
+		let metadata = inner.stats.metadata();
+		let Some(mut writer) = inner.writer.take() else { return Ok(()) };
+		writer.append_key_value_metadata(metadata);
+		let written = writer.close()?;
+		println!("written {} rows to geoparquet", written.num_rows);
+		Ok(())
+	}
+}
+
+/// Flattened hierarchy as GeoParquet, same columns as `table.csv` plus `admin_level`, with the place center as `geometry`.
+/// Name columns are the languages of all `specs`, each once, falling back to `name` like in the first spec listing the language
+pub fn write_hierarchy(path: &Path, specs: &[TableSpec], rows: &[HierarchyRow]) -> anyhow::Result<()> {
+	let mut languages = Vec::<(&str, bool)>::new();
+	for spec in specs {
+		for lang in spec.languages.iter() {
+			if !languages.iter().any(|(l, _)| l == lang) {
+				languages.push((lang, spec.name_fallback.contains(lang)));
+			}
+		}
+	}
+
+	let text = |name: String| Field::new(name, DataType::Utf8, true);
+	let names = |tier: &'static str| languages.iter().map(move |(lang, _)| text(format!("{tier}_{lang}")));
+	let mut fields = vec![text("country_iso".into()), text("country_wikidata".into())];
+	fields.extend(names("country"));
+	fields.extend([text("region_iso".into()), text("region_ref".into()), text("region_wikidata".into())]);
+	fields.extend(names("region"));
+	fields.push(text("city_wikidata".into()));
+	fields.extend(names("city"));
+	fields.extend([
+		Field::new("admin_level", DataType::UInt8, true),
+		Field::new("lon", DataType::Float64, false),
+		Field::new("lat", DataType::Float64, false),
+		Field::new("population", DataType::Int64, true),
+		Field::new("geometry", DataType::Binary, false),
+	]);
+	let schema = Arc::new(Schema::new(fields));
+	let mut writer = create_writer(path, schema.clone())?;
+	let mut stats = GeometryStats::default();
+
+	for chunk in rows.chunks(BATCH_SIZE) {
+		let points = chunk.iter().map(|r| Geometry::Point(geo::Point::new(r.lon, r.lat))).collect::<Vec<_>>();
+		for point in points.iter() {
+			stats.add(point, point.bounding_rect().unwrap());
+		}
+
+		let item = |tier: Tier, value: &dyn Fn(&HierarchyItem) -> Option<&String>| -> ArrayRef {
+			Arc::new(chunk.iter().map(|r| r.item(tier).and_then(value).map(|v| v.as_str())).collect::<StringArray>())
+		};
+		let names = |tier: Tier| languages.iter().map(move |(lang, fallback)| item(tier, &|i| i.name(lang, *fallback)));
+		let mut columns = vec![item(Tier::Country, &|i| i.iso_code.as_ref()), item(Tier::Country, &|i| i.wikidata.as_ref())];
+		columns.extend(names(Tier::Country));
+		columns.extend([item(Tier::Region, &|i| i.iso_code.as_ref()), item(Tier::Region, &|i| i.osm_ref.as_ref()), item(Tier::Region, &|i| i.wikidata.as_ref())]);
+		columns.extend(names(Tier::Region));
+		columns.push(item(Tier::City, &|i| i.wikidata.as_ref()));
+		columns.extend(names(Tier::City));
+		columns.extend::<[ArrayRef; 5]>([
+			Arc::new(chunk.iter().map(|r| r.admin_level).collect::<UInt8Array>()),
+			Arc::new(chunk.iter().map(|r| Some(r.lon)).collect::<Float64Array>()),
+			Arc::new(chunk.iter().map(|r| Some(r.lat)).collect::<Float64Array>()),
+			Arc::new(chunk.iter().map(|r| r.tags.get("population").and_then(|p| parse_population(p))).collect::<Int64Array>()),
+			Arc::new(points.iter().map(|p| Some(to_wkb(p))).collect::<BinaryArray>()),
+		]);
+		let batch = RecordBatch::try_new(schema.clone(), columns)?;
+		writer.write(&batch)?;
+	}
+
+	writer.append_key_value_metadata(stats.metadata());
+	writer.close()?;
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use arrow_array::Array;
+	use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+
+	use super::*;
+
+	fn item(tags: &[(&str, &str)]) -> Option<HierarchyItem> {
+		Some(HierarchyItem {
+			osm_id: "relation/1".into(),
+			iso_code: None,
+			osm_ref: None,
+			wikidata: None,
+			tags: tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+		})
+	}
+
+	#[test]
+	fn hierarchy_name_columns_follow_table_specs() {
+		let spec = |languages: &[&str], name_fallback: &[&str]| TableSpec {
+			languages: languages.iter().map(|l| l.to_string()).collect(),
+			name_fallback: name_fallback.iter().map(|l| l.to_string()).collect(),
+			..Default::default()
+		};
+		let row = HierarchyRow {
+			country: item(&[("name", "Deutschland"), ("name:en", "Germany")]),
+			city: item(&[("name", "München"), ("name:zh", "慕尼黑")]),
+			..Default::default()
+		};
+		let path = std::env::temp_dir().join(format!("rs-osm-parquet-test-{}.parquet", std::process::id()));
+		write_hierarchy(&path, &[spec(&["de", "en"], &["de"]), spec(&["zh", "de"], &[])], &[row]).unwrap();
+		let batch = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap()).unwrap().build().unwrap().next().unwrap().unwrap();
+		std::fs::remove_file(&path).unwrap();
+
+		let names = batch.schema().fields().iter().map(|f| f.name().clone()).filter(|n| n.ends_with("_de") || n.ends_with("_en") || n.ends_with("_zh") || n.ends_with("_ru")).collect::<Vec<_>>();
+		assert_eq!(names, ["country_de", "country_en", "country_zh", "region_de", "region_en", "region_zh", "city_de", "city_en", "city_zh"]);
+		let value = |column: &str| {
+			let array = batch.column_by_name(column).unwrap().as_any().downcast_ref::<StringArray>().unwrap().clone();
+			array.is_valid(0).then(|| array.value(0).to_string())
+		};
+		assert_eq!(value("country_de").as_deref(), Some("Deutschland"));
+		assert_eq!(value("country_en").as_deref(), Some("Germany"));
+		assert_eq!(value("country_zh"), None);
+		assert_eq!(value("city_de").as_deref(), Some("München"));
+		assert_eq!(value("city_zh").as_deref(), Some("慕尼黑"));
+		assert_eq!(value("region_de"), None);
+	}
+}
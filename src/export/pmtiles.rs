@@ -0,0 +1,364 @@
+// https://github.com/protomaps/PMTiles/blob/main/spec/v3/spec.md
+
+use std::{
+	collections::HashMap,
+	fs::File,
+	io::{BufWriter, Write},
+	os::unix::fs::FileExt,
+	path::{Path, PathBuf},
+};
+
+use flate2::{write::GzEncoder, Compression};
+use geo::{BoundingRect, Rect};
+use ::geojson::Feature;
+use parking_lot::Mutex;
+use rayon::prelude::*;
+
+use super::{
+	feature_geometry,
+	mvt::{encode_tile, tile_geometry, tile_range, TileFeature, Value, WorldGeometry},
+	Attributes, FeatureSink, Tier,
+};
+
+const HEADER_LEN: usize = 127;
+const ROOT_DIR_MAX_LEN: usize = 16384 - HEADER_LEN;
+const COMPRESSION_GZIP: u8 = 2;
+const TILE_TYPE_MVT: u8 = 1;
+
+/// Simplification tolerance and the smallest polygon kept, in pixels of a 256px tile
+const SIMPLIFY_PX: f64 = 1.0;
+const MIN_POLYGON_PX: f64 = 2.0;
+
+const LAYERS: [&str; 2] = ["boundaries", "places"];
+/// Tiles encoded at once in `finish`, the rest stays on disk
+const ENCODE_BATCH: usize = 4096;
+/// Identical tiles are mostly the small ones fully inside a polygon, bigger ones aren't looked up to keep the lookup small
+const DEDUP_MAX_LEN: usize = 1024;
+
+/// Hilbert curve position of the tile, counted from the first tile of zoom 0
+pub fn tile_id(z: u8, x: u32, y: u32) -> u64 {
+	let base = ((1u64 << (2 * z as u64)) - 1) / 3;
+	let (mut x, mut y) = (x as u64, y as u64);
+	let mut d = 0;
+	let mut s = (1u64 << z) / 2;
+	while s > 0 {
+		let rx = (x & s > 0) as u64;
+		let ry = (y & s > 0) as u64;
+		d += s * s * ((3 * rx) ^ ry);
+		if ry == 0 {
+			if rx == 1 {
+				x = s - 1 - (x & (s - 1));
+				y = s - 1 - (y & (s - 1));
+			}
+			std::mem::swap(&mut x, &mut y);
+		}
+		s /= 2;
+	}
+	base + d
+}
+
+fn gzip(bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+	let mut encoder = GzEncoder::new(Vec::with_capacity(bytes.len() / 2), Compression::default());
+	encoder.write_all(bytes)?;
+	Ok(encoder.finish()?)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut v: u64) {
+	while v >= 0x80 {
+		out.push((v as u8) | 0x80);
+		v >>= 7;
+	}
+	out.push(v as u8);
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+	tile_id: u64,
+	offset: u64,
+	length: u32,
+	/// 0 for entries pointing to leaf directories
+	run_length: u32,
+}
+
+fn serialize_directory(entries: &[Entry]) -> anyhow::Result<Vec<u8>> {
+	let mut out = Vec::with_capacity(entries.len() * 8);
+	write_varint(&mut out, entries.len() as u64);
+	let mut last_id = 0;
+	for entry in entries {
+		write_varint(&mut out, entry.tile_id - last_id);
+		last_id = entry.tile_id;
+	}
+	entries.iter().for_each(|e| write_varint(&mut out, e.run_length as u64));
+	entries.iter().for_each(|e| write_varint(&mut out, e.length as u64));
+	for (i, entry) in entries.iter().enumerate() {
+		let contiguous = i > 0 && entry.offset == entries[i - 1].offset + entries[i - 1].length as u64;
+		write_varint(&mut out, if contiguous { 0 } else { entry.offset + 1 });
+	}
+	gzip(&out)
+}
+
+/// Root directory and concatenated leaf directories, leaves are used only when the root doesn't fit into the first 16K
+fn build_directories(entries: &[Entry]) -> anyhow::Result<(Vec<u8>, Vec<u8>)> {
+	let root = serialize_directory(entries)?;
+	if root.len() <= ROOT_DIR_MAX_LEN {
+		return Ok((root, Vec::new()));
+	}
+
+	let mut leaf_size = 4096;
+	loop {
+		let mut leaves = Vec::new();
+		let mut root_entries = Vec::new();
+		for chunk in entries.chunks(leaf_size) {
+			let leaf = serialize_directory(chunk)?;
+			root_entries.push(Entry { tile_id: chunk[0].tile_id, offset: leaves.len() as u64, length: leaf.len() as u32, run_length: 0 });
+			leaves.extend_from_slice(&leaf);
+		}
+		let root = serialize_directory(&root_entries)?;
+		if root.len() <= ROOT_DIR_MAX_LEN {
+			return Ok((root, leaves));
+		}
+		leaf_size += leaf_size / 5;
+	}
+}
+
+/// Min zoom of a feature by its tier, bigger cities show up earlier
+fn feature_min_zoom(tier: Tier, point: bool, population: Option<i64>) -> u8 {
+	match (tier, point) {
+		(Tier::Country, _) => 0,
+		(Tier::Region, _) => 2,
+		(Tier::District, _) => 5,
+		(Tier::City, false) => 7,
+		(Tier::City, true) => match population.unwrap_or(0) {
+			1_000_000.. => 2,
+			100_000.. => 4,
+			10_000.. => 6,
+			_ => 8,
+		},
+	}
+}
+
+/// Tile feature with its layer and sort key, bincoded into the spool
+type SpooledFeature = (usize, String, TileFeature);
+
+/// Spooled tile feature: tile id, offset and length in the spool
+#[derive(Clone, Copy)]
+struct SpoolEntry {
+	tile_id: u64,
+	offset: u64,
+	len: u32,
+}
+
+#[derive(Default)]
+struct State {
+	spool: Option<BufWriter<File>>,
+	spool_len: u64,
+	entries: Vec<SpoolEntry>,
+	bounds: Option<Rect>,
+}
+
+/// Mapbox Vector Tiles for zooms `TILES_MINZOOM..=TILES_MAXZOOM` (0 and 8 by default) packed into one PMTiles archive.
+/// Polygons go to the `boundaries` layer and points to `places`. Tile features are spooled to disk until `finish`,
+/// which encodes tiles in tile id order into a second spool, because directories go before tile data
+pub struct PmTilesSink {
+	path: PathBuf,
+	min_zoom: u8,
+	max_zoom: u8,
+	state: Mutex<State>,
+}
+
+impl PmTilesSink {
+	pub fn new(path: &Path) -> anyhow::Result<Self> {
+		let zoom = |key: &str, default: u8| -> anyhow::Result<u8> {
+			match std::env::var(key) {
+				Ok(v) => Ok(v.parse()?),
+				Err(_) => Ok(default),
+			}
+		};
+		let min_zoom = zoom("TILES_MINZOOM", 0)?;
+		let max_zoom = zoom("TILES_MAXZOOM", 8)?;
+		if min_zoom > max_zoom || max_zoom > 15 {
+			anyhow::bail!("wrong tiles zoom range {min_zoom}..{max_zoom}, max zoom is 15");
+		}
+
+		Ok(Self { path: path.to_path_buf(), min_zoom, max_zoom, state: Mutex::new(State::default()) })
+	}
+
+	fn spool_path(&self, kind: &str) -> PathBuf {
+		let mut path = self.path.as_os_str().to_owned();
+		path.push(format!(".{kind}.spool"));
+		path.into()
+	}
+
+	fn metadata(&self) -> serde_json::Value {
+		let fields = serde_json::json!({
+			"osm_id": "String", "name": "String", "name_en": "String", "name_ru": "String", "iso_code": "String",
+			"wikidata": "String", "admin_level": "Number", "population": "Number", "tier": "String",
+		});
+		serde_json::json!({
+			"name": self.path.file_stem().map(|s| s.to_string_lossy().to_string()),
+			"format": "pbf",
+			"type": "overlay",
+			"generator": format!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION")),
+			"minzoom": self.min_zoom,
+			"maxzoom": self.max_zoom,
+			"vector_layers": LAYERS.map(|id| serde_json::json!({
+				"id": id, "fields": fields, "minzoom": self.min_zoom, "maxzoom": self.max_zoom,
+			})),
+		})
+	}
+}
+
+impl FeatureSink for PmTilesSink {
+	fn write(&self, feature: &Feature) -> anyhow::Result<()> {
+		let Some(tier) = Tier::of(feature) else { return Ok(()) };
+		let Some(geometry) = feature_geometry(feature) else { return Ok(()) };
+		let Some(world) = WorldGeometry::from_geo(&geometry) else { return Ok(()) };
+		let Some(rect) = geometry.bounding_rect() else { return Ok(()) };
+		let attributes = Attributes::from_feature(feature);
+		let point = matches!(world, WorldGeometry::Point(_));
+
+		let mut properties = vec![("osm_id", Value::String(attributes.osm_id.clone())), ("tier", Value::String(tier.layer_name().into()))];
+		let strings = [
+			("name", &attributes.name), ("name_en", &attributes.name_en), ("name_ru", &attributes.name_ru),
+			("iso_code", &attributes.iso_code), ("wikidata", &attributes.wikidata),
+		];
+		properties.extend(strings.into_iter().filter_map(|(k, v)| Some((k, Value::String(v.clone()?)))));
+		properties.extend(attributes.admin_level.map(|al| ("admin_level", Value::Int(al as i64))));
+		properties.extend(attributes.population.map(|p| ("population", Value::Int(p))));
+
+		let layer = if point { 1 } else { 0 };
+		let first_zoom = feature_min_zoom(tier, point, attributes.population).clamp(self.min_zoom, self.max_zoom);
+		let mut tiles = Vec::new();
+		for zoom in first_zoom..=self.max_zoom {
+			let Some(generalized) = world.generalize(zoom, SIMPLIFY_PX, MIN_POLYGON_PX) else { continue };
+			let Some(world_rect) = generalized.bounding_rect() else { continue };
+			for (x, y) in tile_range(&world_rect, zoom) {
+				let Some((geom_type, commands)) = tile_geometry(&generalized, zoom, x, y) else { continue };
+				let properties = properties.iter().map(|(k, v)| (k.to_string(), v.clone())).collect();
+				let feature: SpooledFeature = (layer, attributes.osm_id.clone(), TileFeature { geom_type, geometry: commands, properties });
+				tiles.push((tile_id(zoom, x, y), bincode::serialize(&feature)?));
+			}
+		}
+
+		let mut state = self.state.lock();
+		state.bounds = Some(match state.bounds {
+			Some(b) => Rect::new(
+				geo::Coord { x: b.min().x.min(rect.min().x), y: b.min().y.min(rect.min().y) },
+				geo::Coord { x: b.max().x.max(rect.max().x), y: b.max().y.max(rect.max().y) },
+			),
+			None => rect,
+		});
+		if state.spool.is_none() {
+			state.spool = Some(BufWriter::new(File::create(self.spool_path("features"))?));
+		}
+		for (tile_id, data) in tiles {
+			state.spool.as_mut().unwrap().write_all(&data)?;
+			let offset = state.spool_len;
+			state.entries.push(SpoolEntry { tile_id, offset, len: data.len() as u32 });
+			state.spool_len += data.len() as u64;
+		}
+		Ok(())
+	}
+
+	fn finish(&self) -> anyhow::Result<()> {
+		let mut state = self.state.lock();
+		if let Some(mut spool) = state.spool.take() {
+			spool.flush()?;
+		}
+		let mut features = std::mem::take(&mut state.entries);
+		features.par_sort_unstable_by_key(|e| (e.tile_id, e.offset));
+		let spool = File::open(self.spool_path("features")).ok();
+		let tiles = features.chunk_by(|a, b| a.tile_id == b.tile_id).collect::<Vec<_>>();
+
+		let encode = |tile: &[SpoolEntry]| -> anyhow::Result<(u64, Vec<u8>)> {
+			let spool = spool.as_ref().ok_or_else(|| anyhow::anyhow!("pmtiles spool is missing"))?;
+			let mut features = tile.iter().map(|entry| -> anyhow::Result<SpooledFeature> {
+				let mut buf = vec![0u8; entry.len as usize];
+				spool.read_exact_at(&mut buf, entry.offset)?;
+				Ok(bincode::deserialize(&buf)?)
+			}).collect::<anyhow::Result<Vec<_>>>()?;
+			features.sort_by(|a, b| (a.0, &a.1).cmp(&(b.0, &b.1)));
+			let layers = LAYERS.iter().enumerate()
+				.map(|(i, name)| (*name, features.iter().filter(|f| f.0 == i).map(|f| &f.2).collect::<Vec<_>>()))
+				.collect::<Vec<_>>();
+			Ok((tile[0].tile_id, gzip(&encode_tile(&layers))?))
+		};
+
+		// identical tiles (like the ones fully inside a country) are stored once
+		let mut tile_data = BufWriter::new(File::create(self.spool_path("tiles"))?);
+		let mut tile_data_len = 0u64;
+		let mut contents = HashMap::<Vec<u8>, u64>::new();
+		let mut unique = 0u64;
+		let mut entries = Vec::<Entry>::new();
+		for batch in tiles.chunks(ENCODE_BATCH) {
+			let encoded = batch.par_iter().map(|tile| encode(tile)).collect::<anyhow::Result<Vec<_>>>()?;
+			for (id, data) in encoded {
+				let known = (data.len() <= DEDUP_MAX_LEN).then(|| contents.get(&data).copied()).flatten();
+				let offset = match known {
+					Some(offset) => offset,
+					None => {
+						tile_data.write_all(&data)?;
+						let offset = tile_data_len;
+						tile_data_len += data.len() as u64;
+						unique += 1;
+						if data.len() <= DEDUP_MAX_LEN {
+							contents.insert(data.clone(), offset);
+						}
+						offset
+					},
+				};
+				match entries.last_mut() {
+					Some(last) if last.offset == offset && last.tile_id + last.run_length as u64 == id => last.run_length += 1,
+					_ => entries.push(Entry { tile_id: id, offset, length: data.len() as u32, run_length: 1 }),
+				}
+			}
+		}
+		tile_data.flush()?;
+		drop(tile_data);
+		drop(contents);
+
+		let (root, leaves) = build_directories(&entries)?;
+		let metadata = gzip(self.metadata().to_string().as_bytes())?;
+		let bounds = state.bounds.unwrap_or(Rect::new(geo::Coord::zero(), geo::Coord::zero()));
+		let e7 = |v: f64| ((v * 10_000_000.0).round() as i32).to_le_bytes();
+
+		let mut header = Vec::with_capacity(HEADER_LEN);
+		header.extend_from_slice(b"PMTiles");
+		header.push(3);
+		let root_offset = HEADER_LEN as u64;
+		let metadata_offset = root_offset + root.len() as u64;
+		let leaves_offset = metadata_offset + metadata.len() as u64;
+		let data_offset = leaves_offset + leaves.len() as u64;
+		for v in [
+			root_offset, root.len() as u64,
+			metadata_offset, metadata.len() as u64,
+			leaves_offset, leaves.len() as u64,
+			data_offset, tile_data_len,
+			entries.iter().map(|e| e.run_length as u64).sum::<u64>(),
+			entries.len() as u64,
+			unique,
+		] {
+			header.extend_from_slice(&v.to_le_bytes());
+		}
+		header.extend_from_slice(&[1, COMPRESSION_GZIP, COMPRESSION_GZIP, TILE_TYPE_MVT, self.min_zoom, self.max_zoom]);
+		for v in [bounds.min().x, bounds.min().y, bounds.max().x, bounds.max().y] {
+			header.extend_from_slice(&e7(v));
+		}
+		header.push(self.min_zoom);
+		header.extend_from_slice(&e7(bounds.center().x));
+		header.extend_from_slice(&e7(bounds.center().y));
+
+		let mut out = BufWriter::new(File::create(&self.path)?);
+		for part in [&header, &root, &metadata, &leaves] {
+			out.write_all(part)?;
+		}
+		std::io::copy(&mut File::open(self.spool_path("tiles"))?, &mut out)?;
+		out.flush()?;
+		std::fs::remove_file(self.spool_path("tiles"))?;
+		if spool.is_some() {
+			std::fs::remove_file(self.spool_path("features"))?;
+		}
+		println!("written {} tiles ({unique} unique) to {}", tiles.len(), self.path.display());
+		Ok(())
+	}
+}
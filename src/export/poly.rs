@@ -0,0 +1,66 @@
+// https://wiki.openstreetmap.org/wiki/Osmosis/Polygon_Filter_File_Format
+
+use std::{
+	fmt::Write,
+	path::{Path, PathBuf},
+};
+
+use geo::{Geometry, LineString};
+use ::geojson::Feature;
+
+use super::{feature_geometry, safe_file_name, FeatureSink};
+
+/// Writes `<safe id>.poly` for every relation boundary, holes become `!` sections after their outer ring
+pub struct PolySink {
+	dir: PathBuf,
+}
+
+impl PolySink {
+	pub fn new(dir: &Path) -> Self {
+		Self { dir: dir.to_path_buf() }
+	}
+}
+
+fn write_section(out: &mut String, name: &str, ring: &LineString) {
+	out.push_str(name);
+	out.push('\n');
+	for coord in ring.0.iter() {
+		let _ = writeln!(out, "\t{:.7}\t{:.7}", coord.x, coord.y);
+	}
+	out.push_str("END\n");
+}
+
+impl FeatureSink for PolySink {
+	fn write(&self, feature: &Feature) -> anyhow::Result<()> {
+		let Some(::geojson::feature::Id::String(id)) = feature.id.as_ref() else { return Ok(()) };
+		if !id.starts_with("relation/") {
+			return Ok(());
+		}
+		let polygons = match feature_geometry(feature) {
+			Some(Geometry::Polygon(p)) => vec![p],
+			Some(Geometry::MultiPolygon(mp)) => mp.0,
+			_ => return Ok(()),
+		};
+
+		let mut out = String::new();
+		out.push_str(id);
+		out.push('\n');
+		let mut section = 0;
+		for polygon in polygons.iter() {
+			section += 1;
+			write_section(&mut out, &section.to_string(), polygon.exterior());
+			for interior in polygon.interiors() {
+				section += 1;
+				write_section(&mut out, &format!("!{section}"), interior);
+			}
+		}
+		out.push_str("END\n");
+
+		std::fs::write(self.dir.join(format!("{}.poly", safe_file_name(id))), out)?;
+		Ok(())
+	}
+
+	fn finish(&self) -> anyhow::Result<()> {
+		Ok(())
+	}
+}
@@ -0,0 +1,225 @@
+// https://www.postgresql.org/docs/current/sql-copy.html#id-1.9.3.55.9.2
+
+use std::{
+	collections::{hash_map::Entry, HashMap},
+	fs::File,
+	io::{BufRead, BufReader, BufWriter, Write},
+	path::{Path, PathBuf},
+};
+
+use ::geojson::Feature;
+use parking_lot::Mutex;
+
+use super::{feature_geometry, wkb::to_ewkb, Attributes, FeatureSink, Output, Tier};
+
+const SRID: u32 = 4326;
+const TIERS: [Tier; 3] = [Tier::Country, Tier::Region, Tier::City];
+
+const SCHEMA: &str = r#"\set ON_ERROR_STOP on
+BEGIN;
+CREATE EXTENSION IF NOT EXISTS postgis;
+DROP TABLE IF EXISTS cities, regions, countries;
+
+CREATE TABLE countries (
+	osm_id text PRIMARY KEY,
+	name text,
+	name_en text,
+	name_ru text,
+	iso_code text,
+	wikidata text,
+	admin_level smallint,
+	population bigint,
+	tags jsonb,
+	geom geometry(Geometry, 4326)
+);
+CREATE TABLE regions (
+	osm_id text PRIMARY KEY,
+	name text,
+	name_en text,
+	name_ru text,
+	iso_code text,
+	wikidata text,
+	admin_level smallint,
+	population bigint,
+	tags jsonb,
+	geom geometry(Geometry, 4326),
+	country_id text
+);
+CREATE TABLE cities (
+	osm_id text PRIMARY KEY,
+	name text,
+	name_en text,
+	name_ru text,
+	wikidata text,
+	admin_level smallint,
+	population bigint,
+	tags jsonb,
+	geom geometry(Geometry, 4326),
+	region_id text,
+	country_id text
+);
+"#;
+
+// constraints and indexes are created after COPY, it's faster and rows don't have to be ordered
+const CONSTRAINTS: &str = r#"
+ALTER TABLE regions ADD FOREIGN KEY (country_id) REFERENCES countries (osm_id);
+ALTER TABLE cities ADD FOREIGN KEY (region_id) REFERENCES regions (osm_id), ADD FOREIGN KEY (country_id) REFERENCES countries (osm_id);
+CREATE INDEX ON countries USING gist (geom);
+CREATE INDEX ON regions USING gist (geom);
+CREATE INDEX ON cities USING gist (geom);
+CREATE INDEX ON regions (country_id);
+CREATE INDEX ON cities (region_id);
+CREATE INDEX ON cities (country_id);
+COMMIT;
+ANALYZE countries, regions, cities;
+"#;
+
+fn table_columns(tier: Tier) -> &'static str {
+	match tier {
+		Tier::Country => "osm_id, name, name_en, name_ru, iso_code, wikidata, admin_level, population, tags, geom",
+		Tier::Region => "osm_id, name, name_en, name_ru, iso_code, wikidata, admin_level, population, tags, geom, country_id",
+		_ => "osm_id, name, name_en, name_ru, wikidata, admin_level, population, tags, geom, region_id, country_id",
+	}
+}
+
+/// COPY text format value, `\N` is NULL
+fn copy_value(value: Option<&str>) -> String {
+	let Some(value) = value else { return r"\N".into() };
+	let mut escaped = String::with_capacity(value.len());
+	for c in value.chars() {
+		match c {
+			'\\' => escaped.push_str(r"\\"),
+			'\t' => escaped.push_str(r"\t"),
+			'\n' => escaped.push_str(r"\n"),
+			'\r' => escaped.push_str(r"\r"),
+			c => escaped.push(c),
+		}
+	}
+	escaped
+}
+
+fn hex(bytes: &[u8]) -> String {
+	bytes.iter().map(|b| format!("{b:02X}")).collect()
+}
+
+struct TableSpool {
+	out: BufWriter<File>,
+	/// row ids in the order of spooled lines
+	ids: Vec<String>,
+}
+
+#[derive(Default)]
+struct State {
+	tables: HashMap<Tier, TableSpool>,
+	/// tier and parents of every written place, foreign keys are resolved from it on `finish`
+	places: HashMap<String, (Tier, Vec<String>)>,
+}
+
+/// `places.sql` for `psql -f`: schema, COPY data with EWKB geometries and foreign keys of normalized
+/// `countries`, `regions` and `cities` tables. Rows are spooled per table until `finish` when all parents are known
+pub struct PostgisSink {
+	dir: PathBuf,
+	gzip: bool,
+	state: Mutex<State>,
+}
+
+impl PostgisSink {
+	pub fn new(dir: &Path, gzip: bool) -> Self {
+		Self { dir: dir.to_path_buf(), gzip, state: Mutex::new(State::default()) }
+	}
+
+	fn spool_path(&self, tier: Tier) -> PathBuf {
+		self.dir.join(format!("{}.copy.spool", tier.layer_name()))
+	}
+}
+
+impl State {
+	fn parent_of(&self, id: &str, tier: Tier) -> Option<&String> {
+		self.places.get(id)?.1.iter().find(|p| self.places.get(*p).is_some_and(|(t, _)| *t == tier))
+	}
+
+	fn country_of(&self, id: &str) -> Option<&String> {
+		self.parent_of(id, Tier::Country).or_else(|| self.country_of(self.parent_of(id, Tier::Region)?))
+	}
+
+	/// Foreign key columns of a row
+	fn references(&self, tier: Tier, id: &str) -> Vec<Option<&String>> {
+		match tier {
+			Tier::Country => vec![],
+			Tier::Region => vec![self.country_of(id)],
+			_ => vec![self.parent_of(id, Tier::Region), self.country_of(id)],
+		}
+	}
+}
+
+impl FeatureSink for PostgisSink {
+	fn write(&self, feature: &Feature) -> anyhow::Result<()> {
+		let Some(tier) = Tier::of(feature).filter(|t| TIERS.contains(t)) else { return Ok(()) };
+		let Some(geometry) = feature_geometry(feature) else { return Ok(()) };
+		let attributes = Attributes::from_feature(feature);
+
+		let mut values = vec![
+			copy_value(Some(&attributes.osm_id)),
+			copy_value(attributes.name.as_deref()),
+			copy_value(attributes.name_en.as_deref()),
+			copy_value(attributes.name_ru.as_deref()),
+		];
+		if tier != Tier::City {
+			values.push(copy_value(attributes.iso_code.as_deref()));
+		}
+		values.extend([
+			copy_value(attributes.wikidata.as_deref()),
+			copy_value(attributes.admin_level.map(|al| al.to_string()).as_deref()),
+			copy_value(attributes.population.map(|p| p.to_string()).as_deref()),
+			copy_value(feature.properties.as_ref().map(serde_json::to_string).transpose()?.as_deref()),
+			hex(&to_ewkb(&geometry, SRID)),
+		]);
+		let mut line = values.join("\t");
+		line.push('\n');
+
+		let mut state = self.state.lock();
+		if state.places.contains_key(&attributes.osm_id) {
+			eprintln!("postgis: skipping duplicate {}", attributes.osm_id);
+			return Ok(());
+		}
+		let table = match state.tables.entry(tier) {
+			Entry::Occupied(entry) => entry.into_mut(),
+			Entry::Vacant(entry) => entry.insert(TableSpool { out: BufWriter::new(File::create(self.spool_path(tier))?), ids: Vec::new() }),
+		};
+		table.out.write_all(line.as_bytes())?;
+		table.ids.push(attributes.osm_id.clone());
+		state.places.insert(attributes.osm_id, (tier, attributes.parents));
+		Ok(())
+	}
+
+	fn finish(&self) -> anyhow::Result<()> {
+		let mut state = self.state.lock();
+		let mut tables = std::mem::take(&mut state.tables);
+		let mut out = Output::create(&self.dir.join("places.sql"), self.gzip)?;
+		out.write_all(SCHEMA.as_bytes())?;
+
+		for tier in TIERS {
+			let Some(mut table) = tables.remove(&tier) else { continue };
+			table.out.flush()?;
+			drop(table.out);
+
+			writeln!(out, "\nCOPY {} ({}) FROM stdin;", tier.layer_name(), table_columns(tier))?;
+			let spool = BufReader::new(File::open(self.spool_path(tier))?);
+			for (line, id) in spool.lines().zip(table.ids.iter()) {
+				out.write_all(line?.as_bytes())?;
+				for reference in state.references(tier, id) {
+					write!(out, "\t{}", copy_value(reference.map(|r| r.as_str())))?;
+				}
+				out.write_all(b"\n")?;
+			}
+			out.write_all(b"\\.\n")?;
+			std::fs::remove_file(self.spool_path(tier))?;
+			println!("written {} {} to places.sql", table.ids.len(), tier.layer_name());
+		}
+
+		out.write_all(CONSTRAINTS.as_bytes())?;
+		out.finish()?;
+		state.places.clear();
+		Ok(())
+	}
+}
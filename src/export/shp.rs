@@ -0,0 +1,322 @@
+// https://www.esri.com/content/dam/esrisites/sitecore-archive/Files/Pdfs/library/whitepapers/pdfs/shapefile.pdf
+// https://www.clicketyclick.dk/databases/xbase/format/dbf.html
+
+use std::{
+	collections::{BTreeMap, HashMap},
+	fs::File,
+	io::{BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
+	path::{Path, PathBuf},
+};
+
+use chrono::Datelike;
+use geo::{orient::Direction, BoundingRect, Geometry, Orient, Rect};
+use ::geojson::Feature;
+use parking_lot::Mutex;
+
+use super::{feature_geometry, property_string, FeatureSink, Tier};
+
+const SHAPE_POINT: i32 = 1;
+const SHAPE_POLYGON: i32 = 5;
+const HEADER_LEN: u64 = 100;
+/// `.shp` length is stored in 16-bit words as i32
+const MAX_SHP_LEN: u64 = i32::MAX as u64 * 2;
+
+const DBF_MAX_FIELDS: usize = 254;
+const DBF_MAX_FIELD_NAME: usize = 10;
+const DBF_MAX_VALUE: usize = 254;
+const DBF_MAX_RECORD: usize = 65535;
+
+const WGS84_PRJ: &str = r#"GEOGCS["GCS_WGS_1984",DATUM["D_WGS_1984",SPHEROID["WGS_1984",6378137.0,298.257223563]],PRIMEM["Greenwich",0.0],UNIT["Degree",0.0174532925199433]]"#;
+
+#[derive(Default)]
+struct FieldStats {
+	count: usize,
+	max_len: usize,
+}
+
+/// One `.shp`/`.shx` pair being written, attributes wait in `.dbf.spool` until all fields are known
+struct Layer {
+	base: PathBuf,
+	shape_type: i32,
+	shp: BufWriter<File>,
+	shx: BufWriter<File>,
+	spool: BufWriter<File>,
+	shp_len: u64,
+	records: u32,
+	bbox: Option<Rect>,
+	fields: HashMap<String, FieldStats>,
+	truncated_values: u64,
+}
+
+impl Layer {
+	fn create(base: PathBuf, shape_type: i32) -> anyhow::Result<Self> {
+		let mut shp = BufWriter::new(File::create(base.with_extension("shp"))?);
+		let mut shx = BufWriter::new(File::create(base.with_extension("shx"))?);
+		shp.write_all(&[0; HEADER_LEN as usize])?;
+		shx.write_all(&[0; HEADER_LEN as usize])?;
+		let spool = BufWriter::new(File::create(base.with_extension("dbf.spool"))?);
+
+		Ok(Self {
+			base,
+			shape_type,
+			shp,
+			shx,
+			spool,
+			shp_len: HEADER_LEN,
+			records: 0,
+			bbox: None,
+			fields: HashMap::new(),
+			truncated_values: 0,
+		})
+	}
+
+	fn write_header(out: &mut (impl Write + Seek), file_len: u64, shape_type: i32, bbox: &Option<Rect>) -> anyhow::Result<()> {
+		let bbox = bbox.unwrap_or(Rect::new(geo::Coord::zero(), geo::Coord::zero()));
+		out.seek(SeekFrom::Start(0))?;
+		out.write_all(&9994i32.to_be_bytes())?;
+		out.write_all(&[0; 20])?;
+		out.write_all(&((file_len / 2) as i32).to_be_bytes())?;
+		out.write_all(&1000i32.to_le_bytes())?;
+		out.write_all(&shape_type.to_le_bytes())?;
+		for v in [bbox.min().x, bbox.min().y, bbox.max().x, bbox.max().y, 0.0, 0.0, 0.0, 0.0] {
+			out.write_all(&f64::to_le_bytes(v))?;
+		}
+		Ok(())
+	}
+
+	fn finish(mut self) -> anyhow::Result<u32> {
+		let shx_len = HEADER_LEN + self.records as u64 * 8;
+		Self::write_header(&mut self.shp, self.shp_len, self.shape_type, &self.bbox)?;
+		Self::write_header(&mut self.shx, shx_len, self.shape_type, &self.bbox)?;
+		self.shp.flush()?;
+		self.shx.flush()?;
+		self.spool.flush()?;
+		drop(self.spool);
+
+		std::fs::write(self.base.with_extension("prj"), WGS84_PRJ)?;
+		std::fs::write(self.base.with_extension("cpg"), "UTF-8")?;
+
+		let spool_path = self.base.with_extension("dbf.spool");
+		let fields = dbf_fields(self.fields);
+		write_dbf(&self.base.with_extension("dbf"), &spool_path, self.records, &fields)?;
+		std::fs::remove_file(&spool_path)?;
+
+		let mapping = fields.iter().map(|f| (f.name.clone(), f.key.clone())).collect::<BTreeMap<_, _>>();
+		std::fs::write(self.base.with_extension("fields.json"), serde_json::to_string_pretty(&mapping)?)?;
+		if self.truncated_values > 0 {
+			println!("{}: {} values were truncated to {DBF_MAX_VALUE} bytes", self.base.display(), self.truncated_values);
+		}
+
+		Ok(self.records)
+	}
+}
+
+struct DbfField {
+	name: String,
+	key: String,
+	len: usize,
+}
+
+/// Picks fields that fit into dbf limits, most used keys first, and gives them unique names of at most 10 chars
+fn dbf_fields(stats: HashMap<String, FieldStats>) -> Vec<DbfField> {
+	let mut keys = stats.into_iter().collect::<Vec<_>>();
+	keys.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(&b.0)));
+
+	let mut fields = Vec::<DbfField>::new();
+	let mut record_len = 1;
+	let mut dropped = Vec::new();
+	for (key, stat) in keys {
+		let len = stat.max_len.clamp(1, DBF_MAX_VALUE);
+		if fields.len() >= DBF_MAX_FIELDS || record_len + len > DBF_MAX_RECORD {
+			dropped.push(key);
+			continue;
+		}
+
+		let base = key.chars()
+			.map(|c| if c.is_ascii_alphanumeric() || c == '_' { c } else { '_' })
+			.take(DBF_MAX_FIELD_NAME)
+			.collect::<String>();
+		let mut name = base.clone();
+		let mut n = 1;
+		while fields.iter().any(|f| f.name.eq_ignore_ascii_case(&name)) {
+			let suffix = n.to_string();
+			name = format!("{}{suffix}", &base[..base.len().min(DBF_MAX_FIELD_NAME - suffix.len())]);
+			n += 1;
+		}
+
+		record_len += len;
+		fields.push(DbfField { name, key, len });
+	}
+	if !dropped.is_empty() {
+		eprintln!("shapefile: {} keys don't fit into dbf and were dropped: {}", dropped.len(), dropped.join(", "));
+	}
+	fields
+}
+
+fn write_dbf(path: &Path, spool_path: &Path, records: u32, fields: &[DbfField]) -> anyhow::Result<()> {
+	let mut out = BufWriter::new(File::create(path)?);
+	let today = chrono::Local::now();
+	let header_len = 32 + 32 * fields.len() + 1;
+	let record_len = 1 + fields.iter().map(|f| f.len).sum::<usize>();
+
+	out.write_all(&[0x03, (today.year() - 1900) as u8, today.month() as u8, today.day() as u8])?;
+	out.write_all(&records.to_le_bytes())?;
+	out.write_all(&(header_len as u16).to_le_bytes())?;
+	out.write_all(&(record_len as u16).to_le_bytes())?;
+	out.write_all(&[0; 20])?;
+	for field in fields {
+		let mut descriptor = [0u8; 32];
+		descriptor[..field.name.len()].copy_from_slice(field.name.as_bytes());
+		descriptor[11] = b'C';
+		descriptor[16] = field.len as u8;
+		out.write_all(&descriptor)?;
+	}
+	out.write_all(&[0x0D])?;
+
+	let mut record = Vec::with_capacity(record_len);
+	for line in BufReader::new(File::open(spool_path)?).lines() {
+		let values = serde_json::from_str::<HashMap<String, String>>(&line?)?;
+		record.clear();
+		record.push(b' ');
+		for field in fields {
+			let value = values.get(&field.key).map(|v| v.as_bytes()).unwrap_or_default();
+			record.extend_from_slice(value);
+			record.resize(record.len() + field.len - value.len(), b' ');
+		}
+		out.write_all(&record)?;
+	}
+	out.write_all(&[0x1A])?;
+	out.flush()?;
+	Ok(())
+}
+
+/// Cuts value to at most 254 bytes without splitting utf-8 chars
+fn truncate_value(value: &mut String) -> bool {
+	if value.len() <= DBF_MAX_VALUE {
+		return false;
+	}
+	let mut end = DBF_MAX_VALUE;
+	while !value.is_char_boundary(end) {
+		end -= 1;
+	}
+	value.truncate(end);
+	true
+}
+
+/// Shape record content: polygon with all rings as parts (outer clockwise, holes counterclockwise) or point
+fn shape_content(geometry: &Geometry, rect: &Rect) -> Option<(i32, Vec<u8>)> {
+	let polygons = match geometry {
+		Geometry::Point(p) => {
+			let mut content = SHAPE_POINT.to_le_bytes().to_vec();
+			content.extend_from_slice(&p.x().to_le_bytes());
+			content.extend_from_slice(&p.y().to_le_bytes());
+			return Some((SHAPE_POINT, content));
+		},
+		Geometry::Polygon(p) => vec![p.orient(Direction::Reversed)],
+		Geometry::MultiPolygon(mp) => mp.orient(Direction::Reversed).0,
+		_ => return None,
+	};
+
+	let rings = polygons.iter().flat_map(|p| std::iter::once(p.exterior()).chain(p.interiors())).collect::<Vec<_>>();
+	let mut content = SHAPE_POLYGON.to_le_bytes().to_vec();
+	for v in [rect.min().x, rect.min().y, rect.max().x, rect.max().y] {
+		content.extend_from_slice(&v.to_le_bytes());
+	}
+	content.extend_from_slice(&(rings.len() as i32).to_le_bytes());
+	content.extend_from_slice(&(rings.iter().map(|r| r.0.len()).sum::<usize>() as i32).to_le_bytes());
+	let mut start = 0;
+	for ring in rings.iter() {
+		content.extend_from_slice(&(start as i32).to_le_bytes());
+		start += ring.0.len();
+	}
+	for coord in rings.iter().flat_map(|r| r.0.iter()) {
+		content.extend_from_slice(&coord.x.to_le_bytes());
+		content.extend_from_slice(&coord.y.to_le_bytes());
+	}
+	Some((SHAPE_POLYGON, content))
+}
+
+/// Shapefile per tier: `<tier>.shp` for polygons and `<tier>_points.shp` for points,
+/// each with `.shx`, `.dbf` (all properties as text fields), `.prj`, `.cpg`
+/// and `.fields.json` mapping dbf field names back to original keys
+pub struct ShapefileSink {
+	dir: PathBuf,
+	layers: Mutex<HashMap<String, Layer>>,
+}
+
+impl ShapefileSink {
+	pub fn new(dir: &Path) -> Self {
+		Self { dir: dir.to_path_buf(), layers: Mutex::new(HashMap::new()) }
+	}
+}
+
+impl FeatureSink for ShapefileSink {
+	fn write(&self, feature: &Feature) -> anyhow::Result<()> {
+		let Some(tier) = Tier::of(feature) else { return Ok(()) };
+		let Some(geometry) = feature_geometry(feature) else { return Ok(()) };
+		let Some(rect) = geometry.bounding_rect() else { return Ok(()) };
+		let Some((shape_type, content)) = shape_content(&geometry, &rect) else {
+			eprintln!("shapefile: skipping unsupported geometry of {:?}", feature.id);
+			return Ok(());
+		};
+
+		let mut truncated = 0;
+		let values = feature.properties_iter()
+			.map(|(k, v)| {
+				let mut value = property_string(v);
+				if truncate_value(&mut value) { truncated += 1; }
+				(k.clone(), value)
+			})
+			.collect::<HashMap<_, _>>();
+		let mut line = serde_json::to_vec(&values)?;
+		line.push(b'\n');
+
+		let name = match shape_type {
+			SHAPE_POINT => format!("{}_points", tier.layer_name()),
+			_ => tier.layer_name().to_string(),
+		};
+
+		let mut layers = self.layers.lock();
+		if !layers.contains_key(&name) {
+			layers.insert(name.clone(), Layer::create(self.dir.join(&name), shape_type)?);
+		}
+		let layer = layers.get_mut(&name).unwrap();
+
+		if layer.shp_len + 8 + content.len() as u64 > MAX_SHP_LEN {
+			anyhow::bail!("shapefile: {name}.shp would exceed the 4 GiB limit of the format at {:?}", feature.id);
+		}
+		layer.records += 1;
+		let content_words = (content.len() / 2) as i32;
+		layer.shx.write_all(&((layer.shp_len / 2) as i32).to_be_bytes())?;
+		layer.shx.write_all(&content_words.to_be_bytes())?;
+		layer.shp.write_all(&(layer.records as i32).to_be_bytes())?;
+		layer.shp.write_all(&content_words.to_be_bytes())?;
+		layer.shp.write_all(&content)?;
+		layer.shp_len += 8 + content.len() as u64;
+		layer.spool.write_all(&line)?;
+
+		layer.bbox = Some(match layer.bbox {
+			Some(bbox) => Rect::new(
+				geo::Coord { x: bbox.min().x.min(rect.min().x), y: bbox.min().y.min(rect.min().y) },
+				geo::Coord { x: bbox.max().x.max(rect.max().x), y: bbox.max().y.max(rect.max().y) },
+			),
+			None => rect,
+		});
+		for (key, value) in values.iter() {
+			let stats = layer.fields.entry(key.clone()).or_default();
+			stats.count += 1;
+			stats.max_len = stats.max_len.max(value.len());
+		}
+		layer.truncated_values += truncated;
+
+		Ok(())
+	}
+
+	fn finish(&self) -> anyhow::Result<()> {
+		for (name, layer) in self.layers.lock().drain() {
+			let records = layer.finish()?;
+			println!("written {records} shapes to {name}.shp");
+		}
+		Ok(())
+	}
+}
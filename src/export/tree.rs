@@ -0,0 +1,213 @@
+use std::{
+	collections::{HashMap, HashSet},
+	fs::File,
+	io::{BufReader, BufWriter, Read, Write},
+	path::{Path, PathBuf},
+};
+
+use geo::{Area, BoundingRect, Contains, InteriorPoint};
+use ::geojson::Feature;
+use parking_lot::Mutex;
+use rstar::{
+	primitives::{GeomWithData, Rectangle},
+	RTree,
+};
+
+use super::{feature_geometry, safe_file_name, FeatureSink, Tier};
+
+const MAX_SLUG_CHARS: usize = 80;
+
+/// Lowercase name with runs of anything but letters and digits replaced by `-`
+fn slug(name: &str) -> String {
+	let mut slug = String::with_capacity(name.len());
+	for c in name.chars().flat_map(char::to_lowercase) {
+		if c.is_alphanumeric() {
+			slug.push(c);
+		}
+		else if !slug.is_empty() && !slug.ends_with('-') {
+			slug.push('-');
+		}
+	}
+	let mut slug = slug.trim_end_matches('-').chars().take(MAX_SLUG_CHARS).collect::<String>();
+	if slug.ends_with('-') {
+		slug.pop();
+	}
+	slug
+}
+
+struct Entry {
+	/// `relation/123/Name`
+	id: String,
+	name: Option<String>,
+	tier: Option<Tier>,
+	point: Option<geo::Point>,
+	/// osm ids like `relation/123` from the computed hierarchy, only pipeline1 places have them
+	parents: Option<Vec<String>>,
+	/// kept only for tiers that can contain other places, when there's no hierarchy
+	geometry: Option<(geo::Geometry, f64)>,
+	len: usize,
+}
+
+#[derive(Default)]
+struct State {
+	spool: Option<BufWriter<File>>,
+	/// in spool order
+	entries: Vec<Entry>,
+}
+
+/// Writes every feature into `<country>/<region>/<district>/<slug>.geojson`. Pipeline1 places follow their `parents`,
+/// `--extract` features have no hierarchy and go by containment of their center in bigger places.
+/// Features are spooled until `finish`, only geometries of countries, regions and districts without parents stay in memory
+pub struct GeoJsonTreeSink {
+	dir: PathBuf,
+	state: Mutex<State>,
+}
+
+impl GeoJsonTreeSink {
+	pub fn new(dir: &Path) -> Self {
+		Self { dir: dir.to_path_buf(), state: Mutex::new(State::default()) }
+	}
+
+	fn spool_path(&self) -> PathBuf {
+		self.dir.join("geojson_tree.spool")
+	}
+}
+
+/// `center` property written by both pipelines, interior point of the geometry otherwise
+fn feature_point(feature: &Feature, geometry: Option<&geo::Geometry>) -> Option<geo::Point> {
+	let center = feature.property("center").and_then(|c| c.as_str()).and_then(|c| {
+		let (x, y) = c.split_once(',')?;
+		Some(geo::Point::new(x.parse().ok()?, y.parse().ok()?))
+	});
+	center.or_else(|| geometry?.interior_point())
+}
+
+impl FeatureSink for GeoJsonTreeSink {
+	fn write(&self, feature: &Feature) -> anyhow::Result<()> {
+		let id = match feature.id.clone() {
+			Some(::geojson::feature::Id::String(s)) => s,
+			_ => anyhow::bail!("feature without string id"),
+		};
+		let property = |key: &str| feature.property(key).and_then(|v| v.as_str()).map(|v| v.to_string());
+		let tier = Tier::of(feature);
+		let geometry = feature_geometry(feature);
+		let point = feature_point(feature, geometry.as_ref());
+		let parents = feature.property("parents").and_then(|p| p.as_array())
+			.map(|p| p.iter().filter_map(|id| id.as_str().map(|id| id.to_string())).collect::<Vec<_>>());
+		let geometry = geometry
+			.filter(|_| parents.is_none())
+			.filter(|g| tier.is_some_and(|t| t != Tier::City) && matches!(g, geo::Geometry::Polygon(_) | geo::Geometry::MultiPolygon(_)))
+			.map(|g| {
+				let area = g.unsigned_area();
+				(g, area)
+			});
+		let serialized = serde_json::to_vec(feature)?;
+
+		let mut state = self.state.lock();
+		if state.spool.is_none() {
+			state.spool = Some(BufWriter::new(File::create(self.spool_path())?));
+		}
+		state.spool.as_mut().unwrap().write_all(&serialized)?;
+		state.entries.push(Entry {
+			id,
+			name: property("name:en").or_else(|| property("name")),
+			tier,
+			point,
+			parents,
+			geometry,
+			len: serialized.len(),
+		});
+		Ok(())
+	}
+
+	fn finish(&self) -> anyhow::Result<()> {
+		let mut state = self.state.lock();
+		let Some(mut spool) = state.spool.take() else { return Ok(()) };
+		spool.flush()?;
+		drop(spool);
+		let entries = std::mem::take(&mut state.entries);
+
+		let containers = [Tier::Country, Tier::Region, Tier::District].map(|tier| {
+			let envelopes = entries.iter().enumerate()
+				.filter(|(_, e)| e.tier == Some(tier))
+				.filter_map(|(i, e)| {
+					let rect = e.geometry.as_ref()?.0.bounding_rect()?;
+					Some(GeomWithData::new(Rectangle::from_corners(rect.min().x_y().into(), rect.max().x_y().into()), i))
+				})
+				.collect::<Vec<_>>();
+			(tier, RTree::bulk_load(envelopes))
+		});
+
+		let by_osm_id = entries.iter().enumerate()
+			.map(|(i, e)| (e.id.splitn(3, '/').take(2).collect::<Vec<_>>().join("/"), i))
+			.collect::<HashMap<_, _>>();
+
+		// parent is the one of the nearest tier above, from the hierarchy or the smallest container, so every path continues the path of its parent
+		let parents = entries.iter().enumerate().map(|(i, entry)| {
+			if let Some(parents) = &entry.parents {
+				return parents.iter()
+					.filter_map(|id| by_osm_id.get(id).copied())
+					.filter(|j| entries[*j].tier.zip(entry.tier).is_some_and(|(parent, own)| parent < own))
+					.max_by_key(|j| entries[*j].tier);
+			}
+			let point = entry.point?;
+			containers.iter().rev()
+				.filter(|(tier, _)| entry.tier.is_none_or(|t| *tier < t))
+				.find_map(|(_, tree)| tree.locate_all_at_point(&[point.x(), point.y()])
+					.map(|envelope| envelope.data)
+					.filter(|j| *j != i && entries[*j].geometry.as_ref().is_some_and(|(g, _)| g.contains(&point)))
+					.min_by(|a, b| entries[*a].geometry.as_ref().unwrap().1.total_cmp(&entries[*b].geometry.as_ref().unwrap().1)))
+		}).collect::<Vec<_>>();
+
+		// slugs are unique among siblings, ids are sorted so that the same input gives the same names
+		let mut order = (0..entries.len()).collect::<Vec<_>>();
+		order.sort_by(|a, b| entries[*a].id.cmp(&entries[*b].id));
+		let mut taken = HashMap::<Option<usize>, HashSet<String>>::new();
+		let mut slugs = vec![String::new(); entries.len()];
+		for i in order {
+			let entry = &entries[i];
+			let osm_id = safe_file_name(&entry.id.splitn(3, '/').take(2).collect::<Vec<_>>().join("-"));
+			let mut candidate = entry.name.as_deref().map(slug).filter(|s| !s.is_empty()).unwrap_or_else(|| osm_id.clone());
+			let siblings = taken.entry(parents[i]).or_default();
+			if siblings.contains(&candidate) {
+				candidate = format!("{candidate}-{osm_id}");
+			}
+			let base = candidate.clone();
+			let mut n = 1;
+			while siblings.contains(&candidate) {
+				n += 1;
+				candidate = format!("{base}-{n}");
+			}
+			siblings.insert(candidate.clone());
+			slugs[i] = candidate;
+		}
+
+		let mut dirs = vec![None::<PathBuf>; entries.len()];
+		fn dir_of(i: usize, parents: &[Option<usize>], slugs: &[String], dirs: &mut [Option<PathBuf>], root: &Path) -> PathBuf {
+			if let Some(dir) = &dirs[i] {
+				return dir.clone();
+			}
+			let dir = match parents[i] {
+				Some(parent) => dir_of(parent, parents, slugs, dirs, root).join(&slugs[parent]),
+				None => root.to_path_buf(),
+			};
+			dirs[i] = Some(dir.clone());
+			dir
+		}
+
+		let mut spool = BufReader::new(File::open(self.spool_path())?);
+		let mut buf = Vec::new();
+		let mut nested = 0;
+		for (i, entry) in entries.iter().enumerate() {
+			buf.resize(entry.len, 0);
+			spool.read_exact(&mut buf)?;
+			let dir = dir_of(i, &parents, &slugs, &mut dirs, &self.dir);
+			std::fs::create_dir_all(&dir)?;
+			std::fs::write(dir.join(format!("{}.geojson", slugs[i])), &buf)?;
+			nested += parents[i].is_some() as usize;
+		}
+		std::fs::remove_file(self.spool_path())?;
+		println!("written {} features into geojson tree, {nested} of them nested", entries.len());
+		Ok(())
+	}
+}
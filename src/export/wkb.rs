@@ -0,0 +1,91 @@
+// https://libgeos.org/specifications/wkb/
+
+use geo::{Coord, Geometry, LineString, Polygon};
+
+const POINT: u32 = 1;
+const LINESTRING: u32 = 2;
+const POLYGON: u32 = 3;
+const MULTIPOINT: u32 = 4;
+const MULTILINESTRING: u32 = 5;
+const MULTIPOLYGON: u32 = 6;
+const GEOMETRYCOLLECTION: u32 = 7;
+
+const EWKB_SRID_FLAG: u32 = 0x2000_0000;
+
+/// Little endian 2D WKB
+pub fn to_wkb(geometry: &Geometry) -> Vec<u8> {
+	let mut out = Vec::with_capacity(64);
+	write_geometry(&mut out, geometry);
+	out
+}
+
+/// PostGIS EWKB, WKB with `srid` after the type of the outer geometry
+pub fn to_ewkb(geometry: &Geometry, srid: u32) -> Vec<u8> {
+	let mut out = to_wkb(geometry);
+	let geometry_type = u32::from_le_bytes(out[1..5].try_into().unwrap()) | EWKB_SRID_FLAG;
+	out[1..5].copy_from_slice(&geometry_type.to_le_bytes());
+	out.splice(5..5, srid.to_le_bytes());
+	out
+}
+
+fn write_header(out: &mut Vec<u8>, geometry_type: u32) {
+	out.push(1);
+	out.extend_from_slice(&geometry_type.to_le_bytes());
+}
+
+fn write_coord(out: &mut Vec<u8>, coord: &Coord) {
+	out.extend_from_slice(&coord.x.to_le_bytes());
+	out.extend_from_slice(&coord.y.to_le_bytes());
+}
+
+fn write_ring(out: &mut Vec<u8>, ring: &LineString) {
+	out.extend_from_slice(&(ring.0.len() as u32).to_le_bytes());
+	ring.0.iter().for_each(|c| write_coord(out, c));
+}
+
+fn write_polygon(out: &mut Vec<u8>, polygon: &Polygon) {
+	write_header(out, POLYGON);
+	out.extend_from_slice(&(polygon.interiors().len() as u32 + 1).to_le_bytes());
+	write_ring(out, polygon.exterior());
+	polygon.interiors().iter().for_each(|ring| write_ring(out, ring));
+}
+
+fn write_geometry(out: &mut Vec<u8>, geometry: &Geometry) {
+	match geometry {
+		Geometry::Point(p) => {
+			write_header(out, POINT);
+			write_coord(out, &p.0);
+		},
+		Geometry::Line(l) => write_geometry(out, &Geometry::LineString(LineString(vec![l.start, l.end]))),
+		Geometry::LineString(ls) => {
+			write_header(out, LINESTRING);
+			write_ring(out, ls);
+		},
+		Geometry::Polygon(p) => write_polygon(out, p),
+		Geometry::MultiPoint(mp) => {
+			write_header(out, MULTIPOINT);
+			out.extend_from_slice(&(mp.0.len() as u32).to_le_bytes());
+			mp.0.iter().for_each(|p| write_geometry(out, &Geometry::Point(*p)));
+		},
+		Geometry::MultiLineString(mls) => {
+			write_header(out, MULTILINESTRING);
+			out.extend_from_slice(&(mls.0.len() as u32).to_le_bytes());
+			mls.0.iter().for_each(|ls| {
+				write_header(out, LINESTRING);
+				write_ring(out, ls);
+			});
+		},
+		Geometry::MultiPolygon(mp) => {
+			write_header(out, MULTIPOLYGON);
+			out.extend_from_slice(&(mp.0.len() as u32).to_le_bytes());
+			mp.0.iter().for_each(|p| write_polygon(out, p));
+		},
+		Geometry::GeometryCollection(gc) => {
+			write_header(out, GEOMETRYCOLLECTION);
+			out.extend_from_slice(&(gc.0.len() as u32).to_le_bytes());
+			gc.0.iter().for_each(|g| write_geometry(out, g));
+		},
+		Geometry::Rect(r) => write_polygon(out, &r.to_polygon()),
+		Geometry::Triangle(t) => write_polygon(out, &t.to_polygon()),
+	}
+}
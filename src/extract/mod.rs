@@ -1,27 +1,161 @@
-mod converter;
 use std::{
    collections::{HashMap, HashSet}, fs::{File, OpenOptions}, io::Write, iter::FromIterator, ops::Deref, path::Path, str::FromStr, sync::{atomic::{AtomicU32, AtomicU64, Ordering}, Arc, Mutex}, time::{SystemTime, UNIX_EPOCH}
 };
 
-use converter::{convert_nodes_to_points, merge_nodes};
 use geo::{Centroid, CoordsIter, HasDimensions};
 use geojson::Feature;
 use indicatif::ProgressBar;
-use osm_io::osm::model::element;
-use osmpbfreader::{Node, NodeId, OsmPbfReader, Ref, Relation, RelationId, WayId};
+use osmpbfreader::{Node, NodeId, OsmId, OsmObj, OsmPbfReader, Ref, Relation, RelationId, WayId};
 use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use smartstring::SmartString;
 
-pub fn extract_polygons(pbf_path: &str) -> anyhow::Result<()> {
-   let mut pbf = OsmPbfReader::new(File::open(Path::new(pbf_path)).unwrap());
+use crate::{converter, export, input};
 
+pub fn extract_polygons(pbf_path: &str) -> anyhow::Result<()> {
    let output_base_dir = std::path::PathBuf::from_str(&format!("./extracted/al234.extract_full_{}", SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs()))?;
    std::fs::create_dir_all(&output_base_dir)?;
 
-   let elements         = pbf.par_iter().map(Result::unwrap).collect::<Vec<_>>();
-   let elements_count   = elements.len();
-   println!("loaded {elements_count} OSM elements in memory");
+   let sources          = input::Sources::open(pbf_path)?;
+   let elements         = sources.referenced(is_extracted)?;
+   let locations        = sources.locations(&elements)?;
+   println!("loaded {} OSM elements in memory", elements.len());
+
+   let sinks = export::sinks_from_env(&output_base_dir, &["geojson_files"])?;
+   let (recorded_features, centers_found) = write_features(&elements, &locations, None, &sinks)?;
+   for sink in sinks.iter() { sink.finish()?; }
+   println!("Done. Output: {output_base_dir:#?}, {centers_found}/{recorded_features} correct centers were found");
+
+   Ok(())
+}
+
+/// Relations and ways `write_features` may turn into features, the rest is only read when they refer to it
+fn is_extracted(obj: &OsmObj) -> bool {
+   let tags = obj.tags();
+   let is_city = tags.get("place").is_some_and(|pl| ["city", "town"].contains(&pl.as_str()));
+   match obj {
+      OsmObj::Relation(_) => tags.contains_key("name") && (is_city || tags.get("admin_level").is_some_and(|al| [2,3,4,8].contains(&al.parse::<u8>().unwrap_or(0)))),
+      OsmObj::Way(_) => tags.contains_key("name") && (is_city || tags.get("admin_level").is_some_and(|al| [2,3,4,8,9].contains(&al.parse::<u8>().unwrap_or(0)))),
+      OsmObj::Node(_) => false,
+   }
+}
+
+/// Brings `dir` made by `--extract` with `geojson_files` from `previous_src` up to date with `osm_src`.
+/// Only relations and ways reaching changed elements through node → way → relation references are reassembled,
+/// their files are rewritten or removed and every real difference is appended to `dir/changes.log`
+pub fn reextract_changed(previous_src: &str, osm_src: &str, dir: &Path) -> anyhow::Result<()> {
+   let (previous_sources, current_sources) = (input::Sources::open(previous_src)?, input::Sources::open(osm_src)?);
+   let previous = previous_sources.referenced(is_extracted)?;
+   let current = current_sources.referenced(is_extracted)?;
+   let (previous_locations, current_locations) = (previous_sources.locations(&previous)?, current_sources.locations(&current)?);
+   println!("loaded {} previous and {} current OSM elements in memory", previous.len(), current.len());
+
+   let previous_by_id = previous.iter().map(|obj| (obj.id(), obj)).collect::<HashMap<_, _>>();
+   let current_ids = current.iter().map(|obj| obj.id()).collect::<HashSet<_>>();
+   let mut affected = current.iter()
+      .filter(|obj| previous_by_id.get(&obj.id()) != Some(obj))
+      .map(|obj| obj.id())
+      .chain(previous_by_id.keys().filter(|id| !current_ids.contains(id)).copied())
+      .collect::<HashSet<_>>();
+   println!("{} elements changed", affected.len());
+
+   // both versions, a way may have lost a changed node as well as got one. Nodes of ways are compared by their locations
+   for elements in [&previous, &current] {
+      for way in elements.iter().filter_map(|el| el.way()) {
+         if way.nodes.iter().any(|n| affected.contains(&OsmId::Node(*n)) || previous_locations.get(*n) != current_locations.get(*n)) {
+            affected.insert(OsmId::Way(way.id));
+         }
+      }
+   }
+   // relations may be members of relations coming later, so until nothing new is reached
+   loop {
+      let affected_count = affected.len();
+      for elements in [&previous, &current] {
+         for relation in elements.iter().filter_map(|el| el.relation()) {
+            if relation.refs.iter().any(|rf| affected.contains(&rf.member)) { affected.insert(OsmId::Relation(relation.id)); }
+         }
+      }
+      if affected.len() == affected_count { break; }
+   }
+   // a way is skipped or not by the name of its parent relation
+   let member_ways = current.iter()
+      .filter_map(|el| el.relation())
+      .filter(|rel| affected.contains(&OsmId::Relation(rel.id)))
+      .flat_map(|rel| rel.refs.iter().filter(|rf| rf.member.is_way()).map(|rf| rf.member))
+      .collect::<Vec<_>>();
+   affected.extend(member_ways);
+
+   // `relation_123_Name.geojson` back to its element, the name may have changed since
+   let feature_files = |dir: &Path| -> anyhow::Result<HashMap<OsmId, (std::path::PathBuf, String)>> {
+      let mut files = HashMap::new();
+      for entry in std::fs::read_dir(dir)? {
+         let path = entry?.path();
+         let Some(file_name) = path.file_name().map(|n| n.to_string_lossy().into_owned()) else { continue };
+         if !file_name.ends_with(".geojson") { continue; }
+         let mut parts = file_name.splitn(3, '_');
+         let id = match (parts.next(), parts.next().and_then(|id| id.parse::<i64>().ok())) {
+            (Some("relation"), Some(id)) => OsmId::Relation(RelationId(id)),
+            (Some("way"), Some(id)) => OsmId::Way(WayId(id)),
+            _ => continue,
+         };
+         if affected.contains(&id) { files.insert(id, (path.clone(), std::fs::read_to_string(&path)?)); }
+      }
+      Ok(files)
+   };
+   let before = feature_files(dir)?;
+
+   // reassembled files are written aside first, a failure while writing leaves `dir` as it was.
+   // Then each one is renamed over its old path and only after that old files of deleted or renamed features are removed,
+   // so a failure while swapping leaves some features new and some old, but none missing
+   let tmp_dir = dir.join(".reextract.tmp");
+   if tmp_dir.exists() { std::fs::remove_dir_all(&tmp_dir)?; }
+   std::fs::create_dir(&tmp_dir)?;
+   let sinks: [Box<dyn export::FeatureSink>; 1] = [Box::new(export::GeoJsonFilesSink::new(&tmp_dir))];
+   let (recorded_features, after) = match write_features(&current, &current_locations, Some(&affected), &sinks).and_then(|(count, _)| Ok((count, feature_files(&tmp_dir)?))) {
+      Ok(result) => result,
+      Err(e) => {
+         std::fs::remove_dir_all(&tmp_dir);
+         return Err(e);
+      },
+   };
+   let mut replaced = HashSet::new();
+   for (path, _) in after.values() {
+      let target = dir.join(path.file_name().unwrap());
+      std::fs::rename(path, &target)?;
+      replaced.insert(target);
+   }
+   for (path, _) in before.values() {
+      if !replaced.contains(path) { std::fs::remove_file(path)?; }
+   }
+   std::fs::remove_dir(&tmp_dir)?;
+
+   let feature_id = |content: &str| -> String {
+      serde_json::from_str::<serde_json::Value>(content).ok()
+         .and_then(|feature| feature.get("id")?.as_str().map(str::to_owned))
+         .unwrap_or_default()
+   };
+   let mut changes = Vec::new();
+   for (id, (_, content)) in after.iter() {
+      match before.get(id) {
+         None => changes.push(("added", feature_id(content))),
+         Some((_, old_content)) if old_content != content => changes.push(("modified", feature_id(content))),
+         Some(_) => {},
+      }
+   }
+   changes.extend(before.iter().filter(|(id, _)| !after.contains_key(id)).map(|(_, (_, content))| ("deleted", feature_id(content))));
+   changes.sort_by(|a, b| a.1.cmp(&b.1));
+
+   let now = chrono::Local::now().to_rfc3339();
+   let mut log = OpenOptions::new().create(true).append(true).open(dir.join("changes.log"))?;
+   for (kind, id) in changes.iter() { writeln!(log, "{now}\t{kind}\t{id}")?; }
+   println!("Done. {recorded_features} features reassembled, {} of them changed, see {:#?}", changes.len(), dir.join("changes.log"));
 
+   Ok(())
+}
+
+/// Assembles boundaries of `elements` into `sinks`, only relations and ways from `only` when it's set.
+/// Returns counts of written features and of those centered by a member node
+fn write_features(elements: &[OsmObj], locations: &input::Locations, only: Option<&HashSet<OsmId>>, sinks: &[Box<dyn export::FeatureSink>]) -> anyhow::Result<(u32, u32)> {
+   let elements_count   = elements.len();
    let recorded_features = AtomicU32::new(0);
    let centers_found = AtomicU32::new(0);
    
@@ -32,11 +166,7 @@ pub fn extract_polygons(pbf_path: &str) -> anyhow::Result<()> {
    }
 
    let write_feature = |feature: Feature| -> anyhow::Result<()> {
-      let mut safe_name = match feature.id.clone().unwrap() { geojson::feature::Id::String(s) => s, _ => unreachable!() };
-      safe_name.retain(|c| !r#"\\&:<>|*"#.contains(c));
-      safe_name = safe_name.replace("/", "_");
-      let file_name = format!("{}.geojson", safe_name);
-      std::fs::write(output_base_dir.join(&file_name), feature.to_string())?;
+      for sink in sinks.iter() { sink.write(&feature)?; }
       recorded_features.fetch_add(1, Ordering::Relaxed);
       Ok(())
    };
@@ -52,6 +182,11 @@ pub fn extract_polygons(pbf_path: &str) -> anyhow::Result<()> {
             || relation.tags.get("admin_level").is_some_and(|al| [2,3,4,8].contains(&al.parse::<u8>().unwrap_or(0)));
             
          if !has_right_tags { return None; }
+         if only.is_some_and(|only| !only.contains(&OsmId::Relation(relation.id))) {
+            // untouched one isn't written, it's only recorded so that its ways stay skipped
+            cached_relations.lock().unwrap().push(CachedRelation { name, rel_id: relation.id.0, refs: relation.refs.clone() });
+            return None;
+         }
 
          let relation_way_ids = relation.refs.iter().filter_map(|rref| rref.member.way().map(|way| way.0)).collect::<Vec<_>>();
          return Some((relation, relation_way_ids)); 
@@ -73,28 +208,28 @@ pub fn extract_polygons(pbf_path: &str) -> anyhow::Result<()> {
          let needed_nodes_ids = ways_ids_to_node_ids.values().flatten().collect::<HashSet<_>>();
 
          println!("getting nodes of each relation way");
-         elements.par_iter().filter_map(|el| {
-            let node = el.node().filter(|node| { 
-               needed_nodes_ids.contains(&node.id.0)
-            })?;
-            Some((node.id.0, node))
-         }).collect::<HashMap<_, _>>()
+         needed_nodes_ids.into_par_iter().filter_map(|node_id| Some((*node_id, locations.node(NodeId(*node_id))?))).collect::<HashMap<_, _>>()
       };
 
-      println!("mapping relations to nodes");
-      let realtions_to_ways_nodes = relations_to_way_ids.par_iter().map(|(rel, way_ids)| {
-
-         let nodes = ways_ids_to_node_ids.iter().filter_map(|(way_id, node_ids)| {
-         if !way_ids.contains(way_id) { return None; }
-
-         node_ids.iter()
-               .filter_map(|node_id| node_ids_to_nodes.get(node_id))
-               .collect::<Vec<_>>().into()
+      // member ways or nodes outside of the source extract, a part of them would make a broken polygon
+      let incomplete_relations = relations_to_way_ids.par_iter().filter(|(_, way_ids)| way_ids.iter().any(|way_id|
+         ways_ids_to_node_ids.get(way_id).is_none_or(|node_ids| node_ids.iter().any(|node_id| !node_ids_to_nodes.contains_key(node_id)))
+      )).map(|(rel, _)| rel.id).collect::<HashSet<_>>();
+      if !incomplete_relations.is_empty() {
+         println!("skipping {} incomplete relations, cut at extract edges?", incomplete_relations.len());
+      }
 
+      println!("mapping relations to nodes");
+      let realtions_to_ways_nodes = relations_to_way_ids.par_iter().filter(|(rel, _)| !incomplete_relations.contains(&rel.id)).map(|(rel, _)| {
+         let nodes = rel.refs.iter().filter_map(|rf| {
+            let node_ids = ways_ids_to_node_ids.get(&rf.member.way()?.0)?;
+            let nodes = node_ids.iter()
+               .filter_map(|node_id| node_ids_to_nodes.get(node_id).map(|node| (*node).clone()))
+               .collect::<Vec<_>>();
+            Some((rf.role.to_string(), nodes))
          }).collect::<Vec<_>>();
 
          return (rel, nodes)
-         
       }).collect::<HashMap<_, _>>();
 
       let pb = ProgressBar::new(realtions_to_ways_nodes.len().try_into().unwrap());
@@ -107,18 +242,10 @@ pub fn extract_polygons(pbf_path: &str) -> anyhow::Result<()> {
          let id         = format!("relation/{}/{}", relation.id.0, &name);
          let url        = format!("https://www.openstreetmap.org/relation/{}", relation.id.0);
 
-         let coords = nnodes.into_iter().map(|wn| 
-            wn.iter().map(|node| geo::Coord { x: node.lon(), y: node.lat() }).collect::<Vec<_>>()
-         ).collect::<Vec<_>>();
-
-         let polygons = coords.iter().map(|points| geo::Polygon::new(geo::LineString(points.to_vec()), vec![])).collect::<Vec<_>>();
-         if polygons.is_empty() {
+         let Some(geo_geometry) = converter::build_multipolygon(nnodes.clone()) else {
             // eprintln!("skipping empty polygon {id} {url}");
             return Ok(());
-         }
-         let geo_geometry = (polygons.len() == 1)
-            .then(||             geo::Geometry::Polygon(polygons[0].clone()))
-            .unwrap_or(geo::Geometry::MultiPolygon(geo::MultiPolygon(polygons)));
+         };
 
          let mut properties = geojson::JsonObject::from_iter( 
             relation.tags.iter().map(|(key, value)| (key.to_string(), serde_json::to_value(value).unwrap()))
@@ -173,10 +300,11 @@ pub fn extract_polygons(pbf_path: &str) -> anyhow::Result<()> {
          let way = el.way()?;
          let name = way.tags.get("name")?;
 
-         let has_right_tags = way.tags.get("place").is_some_and(|pl| ["city", "town"].contains(&pl.as_str()));
+         let has_right_tags = way.tags.get("place").is_some_and(|pl| ["city", "town"].contains(&pl.as_str()))
             || way.tags.get("admin_level").is_some_and(|al| [2,3,4,8,9].contains(&al.parse::<u8>().unwrap_or(0)));
             
          if !has_right_tags { return None; }
+         if only.is_some_and(|only| !only.contains(&OsmId::Way(way.id))) { return None; }
 
          let parent_relations = cached_relations.iter().filter(|rel|
             rel.refs.iter().any(|rref| rref.member.way().is_some_and(|w| w == way.id))
@@ -198,10 +326,7 @@ pub fn extract_polygons(pbf_path: &str) -> anyhow::Result<()> {
       let node_ids_to_nodes = {
          let needed_nodes_ids = ways_to_node_ids.values().flatten().collect::<HashSet<_>>();
          println!("getting nodes of each way");
-         elements.par_iter().filter_map(|el| {
-            let node = el.node().filter(|node| { needed_nodes_ids.contains(&node.id) })?;
-            Some((node.id, node))
-         }).collect::<HashMap<_, _>>()
+         needed_nodes_ids.into_par_iter().filter_map(|node_id| Some((*node_id, locations.node(*node_id)?))).collect::<HashMap<_, _>>()
       };
 
       let ways_to_nodes = ways_to_node_ids.par_iter().map(|(rel, node_ids)| {
@@ -219,7 +344,10 @@ pub fn extract_polygons(pbf_path: &str) -> anyhow::Result<()> {
          let id   = format!("way/{}/{}", way.id.0, &name);
          let url  = format!("https://www.openstreetmap.org/way/{}", way.id.0);
 
-         
+         if nodes.len() != way.nodes.len() {
+            eprintln!("skipping incomplete way {id} {url}");
+            return Ok(());
+         }
          let exterior = geo::LineString(nodes.into_iter().map(|node| geo::Coord { x: node.lon(), y: node.lat() }).collect::<Vec<_>>());
 
          if exterior.is_empty() {
@@ -260,9 +388,7 @@ pub fn extract_polygons(pbf_path: &str) -> anyhow::Result<()> {
          Ok(())
       })?;
    }
-   println!("Done. Output: {output_base_dir:#?}, {}/{} correct centers were found", centers_found.load(Ordering::Relaxed), recorded_features.load(Ordering::Relaxed));
-
-   Ok(())
+   Ok((recorded_features.into_inner(), centers_found.into_inner()))
 }
 
 #[derive(Clone)]
@@ -0,0 +1,575 @@
+// https://wiki.openstreetmap.org/wiki/PBF_Format#File_format
+
+use std::{
+	fs::File,
+	io::Cursor,
+	os::unix::fs::FileExt,
+	path::Path,
+	sync::atomic::{AtomicU8, Ordering},
+};
+
+use indicatif::ProgressBar;
+use osmpbfreader::{
+	blocks, groups,
+	osmformat::{self, PrimitiveBlock},
+	OsmObj, OsmPbfReader, Way,
+};
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+
+use super::{Format, Meta, Source, Sources};
+use crate::locations_on_ways::LocatedWay;
+
+/// Element kinds of a block as bits, zero until the block is read
+const UNKNOWN: u8 = 0;
+const NODES: u8 = 1;
+const WAYS: u8 = 2;
+const RELATIONS: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+	Node,
+	Way,
+	Relation,
+}
+
+impl Kind {
+	fn bit(self) -> u8 {
+		match self {
+			Kind::Node => NODES,
+			Kind::Way => WAYS,
+			Kind::Relation => RELATIONS,
+		}
+	}
+
+	fn of(obj: &OsmObj) -> Self {
+		match obj {
+			OsmObj::Node(_) => Kind::Node,
+			OsmObj::Way(_) => Kind::Way,
+			OsmObj::Relation(_) => Kind::Relation,
+		}
+	}
+}
+
+struct Blob {
+	/// of the length prefix
+	offset: u64,
+	/// with the prefix and `BlobHeader`
+	size: u64,
+	kinds: AtomicU8,
+}
+
+/// `OSMData` blobs of a PBF file with kinds of elements they hold, found by reading only blob headers.
+/// Kinds of files sorted by type then id come from a binary search over a few blocks, otherwise they are learned by the first pass
+pub struct BlobIndex {
+	file: File,
+	blobs: Vec<Blob>,
+}
+
+fn varint(data: &[u8], pos: &mut usize) -> anyhow::Result<u64> {
+	let mut value = 0u64;
+	for shift in (0..64).step_by(7) {
+		let byte = *data.get(*pos).ok_or_else(|| anyhow::anyhow!("protobuf message ends inside a number"))?;
+		*pos += 1;
+		value |= ((byte & 0x7f) as u64) << shift;
+		if byte & 0x80 == 0 {
+			return Ok(value);
+		}
+	}
+	anyhow::bail!("protobuf number is too long")
+}
+
+/// Packed delta coded `sint64`, as `lat` and `lon` of ways
+fn packed_deltas(data: &[u8]) -> anyhow::Result<Vec<i64>> {
+	let (mut values, mut pos, mut current) = (Vec::new(), 0, 0i64);
+	while pos < data.len() {
+		let zigzag = varint(data, &mut pos)?;
+		current += (zigzag >> 1) as i64 ^ -((zigzag & 1) as i64);
+		values.push(current);
+	}
+	Ok(values)
+}
+
+/// `LocationsOnWays` puts node coordinates into fields 9 and 10 of `Way`, osmpbfreader keeps them as unknown fields
+fn located_ways(block: &PrimitiveBlock) -> anyhow::Result<Vec<LocatedWay>> {
+	let to_decimicro = |offset: i64, value: i64| ((offset + block.get_granularity() as i64 * value) / 100) as i32;
+	let mut result = Vec::new();
+	for group in block.get_primitivegroup() {
+		for (raw, way) in group.get_ways().iter().zip(groups::ways(group, block)) {
+			let coordinates = |field: u32| -> anyhow::Result<Vec<i64>> {
+				let Some(values) = raw.unknown_fields.get(field) else { return Ok(vec![]) };
+				packed_deltas(&values.length_delimited.concat())
+			};
+			let (lats, lons) = (coordinates(9)?, coordinates(10)?);
+			if lats.len() != way.nodes.len() || lons.len() != way.nodes.len() {
+				anyhow::bail!("way {} has {} locations for {} nodes", way.id.0, lats.len().min(lons.len()), way.nodes.len());
+			}
+			let locations = lats.into_iter().zip(lons)
+				.map(|(lat, lon)| (to_decimicro(block.get_lat_offset(), lat), to_decimicro(block.get_lon_offset(), lon)))
+				.collect();
+			result.push((way, locations));
+		}
+	}
+	Ok(result)
+}
+
+/// `Info` of an element with its user still a string table index and timestamp in `date_granularity` units
+#[derive(Clone, Copy, Default)]
+struct RawInfo {
+	present: bool,
+	version: i32,
+	timestamp: i64,
+	changeset: i64,
+	uid: i32,
+	user_sid: usize,
+	visible: bool,
+}
+
+impl RawInfo {
+	fn of(info: &osmformat::Info) -> Self {
+		Self {
+			present: true,
+			version: info.get_version(),
+			timestamp: info.get_timestamp(),
+			changeset: info.get_changeset(),
+			uid: info.get_uid(),
+			user_sid: info.get_user_sid() as usize,
+			visible: !info.has_visible() || info.get_visible(),
+		}
+	}
+}
+
+/// Metadata of an element as read, looked up in its block only when asked for
+pub struct Info<'a> {
+	block: Option<&'a PrimitiveBlock>,
+	raw: RawInfo,
+}
+
+impl Info<'_> {
+	/// Of an element from a file without metadata
+	pub const NONE: Info<'static> = Info { block: None, raw: RawInfo { present: false, version: 0, timestamp: 0, changeset: 0, uid: 0, user_sid: 0, visible: true } };
+
+	pub fn meta(&self) -> Meta {
+		let (Some(block), true) = (self.block, self.raw.present) else { return Meta::default() };
+		let raw = self.raw;
+		Meta {
+			version: raw.version,
+			timestamp: raw.timestamp * block.get_date_granularity() as i64,
+			changeset: raw.changeset,
+			uid: raw.uid,
+			user: block.get_stringtable().get_s().get(raw.user_sid).map(|s| String::from_utf8_lossy(s).into_owned()).unwrap_or_default(),
+			visible: raw.visible,
+		}
+	}
+}
+
+/// `Info` of elements of `kind` in the order `blocks::nodes`, `ways` and `relations` give them: simple then dense nodes of every group
+fn infos(block: &PrimitiveBlock, kind: Kind) -> Vec<RawInfo> {
+	let of = |has: bool, info: &osmformat::Info| if has { RawInfo::of(info) } else { RawInfo::default() };
+	let mut result = Vec::new();
+	for group in block.get_primitivegroup() {
+		match kind {
+			Kind::Node => {
+				result.extend(group.get_nodes().iter().map(|n| of(n.has_info(), n.get_info())));
+				let (dense, info) = (group.get_dense(), group.get_dense().get_denseinfo());
+				let (mut timestamp, mut changeset, mut uid, mut user_sid) = (0, 0, 0, 0);
+				for i in 0..dense.get_id().len() {
+					if i >= info.get_version().len() {
+						result.push(RawInfo::default());
+						continue;
+					}
+					// all but version are delta coded
+					timestamp += info.get_timestamp().get(i).copied().unwrap_or_default();
+					changeset += info.get_changeset().get(i).copied().unwrap_or_default();
+					uid += info.get_uid().get(i).copied().unwrap_or_default();
+					user_sid += info.get_user_sid().get(i).copied().unwrap_or_default();
+					result.push(RawInfo {
+						present: true,
+						version: info.get_version()[i],
+						timestamp,
+						changeset,
+						uid,
+						user_sid: user_sid as usize,
+						visible: info.get_visible().get(i).copied().unwrap_or(true),
+					});
+				}
+			},
+			Kind::Way => result.extend(group.get_ways().iter().map(|w| of(w.has_info(), w.get_info()))),
+			Kind::Relation => result.extend(group.get_relations().iter().map(|r| of(r.has_info(), r.get_info()))),
+		}
+	}
+	result
+}
+
+fn optional_features(path: &Path) -> anyhow::Result<Vec<String>> {
+	Ok(osm_io::osm::pbf::reader::Reader::new(path)?.info().optional_features().clone())
+}
+
+impl Source {
+	/// PBF made by `osmium add-locations-to-ways` or `--cache` with `CACHE_LOCATIONS_ON_WAYS`
+	pub fn locations_on_ways(&self) -> anyhow::Result<bool> {
+		Ok(self.format == Format::Pbf && optional_features(&self.path)?.iter().any(|f| f == "LocationsOnWays"))
+	}
+}
+
+/// `type` and `datasize` of a `BlobHeader` message
+fn blob_header(data: &[u8]) -> anyhow::Result<(String, u64)> {
+	let (mut blob_type, mut datasize, mut pos) = (String::new(), None, 0);
+	while pos < data.len() {
+		let key = varint(data, &mut pos)?;
+		match (key >> 3, key & 7) {
+			(3, 0) => datasize = Some(varint(data, &mut pos)?),
+			(field, 2) => {
+				let len = varint(data, &mut pos)? as usize;
+				let value = data.get(pos..pos + len).ok_or_else(|| anyhow::anyhow!("BlobHeader field is out of bounds"))?;
+				if field == 1 {
+					blob_type = String::from_utf8_lossy(value).into_owned();
+				}
+				pos += len;
+			},
+			(_, 0) => { varint(data, &mut pos)?; },
+			(field, wire_type) => anyhow::bail!("unexpected BlobHeader field {field} of wire type {wire_type}"),
+		}
+	}
+	Ok((blob_type, datasize.ok_or_else(|| anyhow::anyhow!("BlobHeader without datasize"))?))
+}
+
+fn kinds_of(block: &PrimitiveBlock) -> u8 {
+	block.get_primitivegroup().iter().fold(UNKNOWN, |kinds, group| {
+		kinds
+			| if group.has_dense() || !group.get_nodes().is_empty() { NODES } else { 0 }
+			| if !group.get_ways().is_empty() { WAYS } else { 0 }
+			| if !group.get_relations().is_empty() { RELATIONS } else { 0 }
+	})
+}
+
+impl BlobIndex {
+	pub fn build(path: &Path) -> anyhow::Result<Self> {
+		let file = File::open(path)?;
+		let len = file.metadata()?.len();
+		let mut blobs = Vec::new();
+		let mut offset = 0;
+		while offset < len {
+			let mut prefix = [0u8; 4];
+			file.read_exact_at(&mut prefix, offset)?;
+			let header_len = u32::from_be_bytes(prefix) as u64;
+			let mut header = vec![0u8; header_len as usize];
+			file.read_exact_at(&mut header, offset + 4)?;
+			let (blob_type, datasize) = blob_header(&header)?;
+			let size = 4 + header_len + datasize;
+			if blob_type == "OSMData" {
+				blobs.push(Blob { offset, size, kinds: AtomicU8::new(UNKNOWN) });
+			}
+			offset += size;
+		}
+
+		let index = Self { file, blobs };
+		if optional_features(path)?.iter().any(|f| f == "Sort.Type_then_ID") {
+			index.classify_sorted()?;
+		}
+		Ok(index)
+	}
+
+	/// Nodes, then ways, then relations: only blocks at the borders may mix kinds
+	fn classify_sorted(&self) -> anyhow::Result<()> {
+		let first_with = |kinds: u8| -> anyhow::Result<usize> {
+			let (mut low, mut high) = (0, self.blobs.len());
+			while low < high {
+				let mid = (low + high) / 2;
+				if self.kinds(mid)? & kinds != 0 { high = mid } else { low = mid + 1 }
+			}
+			Ok(low)
+		};
+		let first_way = first_with(WAYS | RELATIONS)?;
+		let first_relation = first_with(RELATIONS)?;
+		for (i, blob) in self.blobs.iter().enumerate() {
+			let kinds = match i {
+				_ if blob.kinds.load(Ordering::Relaxed) != UNKNOWN => continue,
+				i if i < first_way => NODES,
+				i if i < first_relation => WAYS,
+				_ => RELATIONS,
+			};
+			blob.kinds.store(kinds, Ordering::Relaxed);
+		}
+		Ok(())
+	}
+
+	fn kinds(&self, i: usize) -> anyhow::Result<u8> {
+		let kinds = self.blobs[i].kinds.load(Ordering::Relaxed);
+		if kinds != UNKNOWN {
+			return Ok(kinds);
+		}
+		let kinds = kinds_of(&self.block(i)?);
+		self.blobs[i].kinds.store(kinds, Ordering::Relaxed);
+		Ok(kinds)
+	}
+
+	fn block(&self, i: usize) -> anyhow::Result<PrimitiveBlock> {
+		let blob = &self.blobs[i];
+		let mut data = vec![0u8; blob.size as usize];
+		self.file.read_exact_at(&mut data, blob.offset)?;
+		let blob = OsmPbfReader::new(Cursor::new(data)).blobs().next().ok_or_else(|| anyhow::anyhow!("empty PBF blob"))??;
+		Ok(osmpbfreader::primitive_block_from_blob(&blob)?)
+	}
+
+	fn may_hold(&self, i: usize, kind: Kind) -> bool {
+		let kinds = self.blobs[i].kinds.load(Ordering::Relaxed);
+		kinds == UNKNOWN || kinds & kind.bit() != 0
+	}
+
+	/// Blocks a pass over `kind` reads
+	pub fn blocks(&self, kind: Kind) -> usize {
+		(0..self.blobs.len()).filter(|i| self.may_hold(*i, kind)).count()
+	}
+
+	/// Elements of `kind` with their metadata passed through `f`, blocks known to hold none of them are skipped
+	pub fn filter_map<T: Send>(&self, kind: Kind, f: impl Fn(OsmObj, Info) -> Option<T> + Sync + Send, bar: &ProgressBar) -> anyhow::Result<Vec<T>> {
+		let results = self.blobs.par_iter().enumerate()
+			.filter(|(i, _)| self.may_hold(*i, kind))
+			.map(|(i, blob)| -> anyhow::Result<Vec<T>> {
+				let block = self.block(i)?;
+				blob.kinds.store(kinds_of(&block), Ordering::Relaxed);
+				let info = |raw| Info { block: Some(&block), raw };
+				let infos = infos(&block, kind);
+				let result = match kind {
+					Kind::Node => blocks::nodes(&block).zip(infos).filter_map(|(n, raw)| f(OsmObj::Node(n), info(raw))).collect(),
+					Kind::Way => blocks::ways(&block).zip(infos).filter_map(|(w, raw)| f(OsmObj::Way(w), info(raw))).collect(),
+					Kind::Relation => blocks::relations(&block).zip(infos).filter_map(|(r, raw)| f(OsmObj::Relation(r), info(raw))).collect(),
+				};
+				bar.inc(1);
+				Ok(result)
+			})
+			.collect::<anyhow::Result<Vec<_>>>()?;
+		Ok(results.into_iter().flatten().collect())
+	}
+
+	/// Ways with decimicro `(lat, lon)` of their nodes passed through `f`, for files with `LocationsOnWays`
+	pub fn located_ways<T: Send>(&self, f: impl Fn(Way, Vec<(i32, i32)>, Info) -> Option<T> + Sync + Send, bar: &ProgressBar) -> anyhow::Result<Vec<T>> {
+		let results = self.blobs.par_iter().enumerate()
+			.filter(|(i, _)| self.may_hold(*i, Kind::Way))
+			.map(|(i, blob)| -> anyhow::Result<Vec<T>> {
+				let block = self.block(i)?;
+				blob.kinds.store(kinds_of(&block), Ordering::Relaxed);
+				let result = located_ways(&block)?.into_iter().zip(infos(&block, Kind::Way))
+					.filter_map(|((way, locations), raw)| f(way, locations, Info { block: Some(&block), raw }))
+					.collect();
+				bar.inc(1);
+				Ok(result)
+			})
+			.collect::<anyhow::Result<Vec<_>>>()?;
+		Ok(results.into_iter().flatten().collect())
+	}
+}
+
+/// Inputs read one kind of elements per pass, PBF ones through a `BlobIndex`, others whole every time
+pub struct KindPasses<'a> {
+	inputs: Vec<(&'a Source, Option<BlobIndex>)>,
+	locations_on_ways: bool,
+}
+
+impl Sources {
+	pub fn passes(&self) -> anyhow::Result<KindPasses<'_>> {
+		let inputs = self.0.iter()
+			.map(|source| Ok((source, (source.format == Format::Pbf).then(|| BlobIndex::build(&source.path)).transpose()?)))
+			.collect::<anyhow::Result<_>>()?;
+		let locations_on_ways = self.0.iter().map(Source::locations_on_ways).collect::<anyhow::Result<Vec<_>>>()?.into_iter().all(|low| low);
+		Ok(KindPasses { inputs, locations_on_ways })
+	}
+}
+
+impl KindPasses<'_> {
+	/// PBF blocks a pass over `kind` reads, for progress
+	pub fn blocks(&self, kind: Kind) -> u64 {
+		self.inputs.iter().filter_map(|(_, index)| index.as_ref()).map(|index| index.blocks(kind) as u64).sum()
+	}
+
+	/// Metadata only comes from PBF, elements of other formats have `Info::NONE`
+	pub fn filter_map<T: Send>(&self, kind: Kind, f: impl Fn(OsmObj, Info) -> Option<T> + Sync + Send, bar: &ProgressBar) -> anyhow::Result<Vec<T>> {
+		let mut result = Vec::new();
+		for (source, index) in self.inputs.iter() {
+			result.append(&mut match index {
+				Some(index) => index.filter_map(kind, &f, bar)?,
+				None => source.filter_map(|obj| (Kind::of(&obj) == kind).then(|| f(obj, Info::NONE)).flatten())?,
+			});
+		}
+		Ok(result)
+	}
+
+	/// Every input has `LocationsOnWays`, so `located_ways` works and way nodes needn't be read
+	pub fn locations_on_ways(&self) -> bool {
+		self.locations_on_ways
+	}
+
+	/// See `BlobIndex::located_ways`
+	pub fn located_ways<T: Send>(&self, f: impl Fn(Way, Vec<(i32, i32)>, Info) -> Option<T> + Sync + Send, bar: &ProgressBar) -> anyhow::Result<Vec<T>> {
+		if !self.locations_on_ways {
+			anyhow::bail!("not every input has LocationsOnWays");
+		}
+		let mut result = Vec::new();
+		for index in self.inputs.iter().filter_map(|(_, index)| index.as_ref()) {
+			result.append(&mut index.located_ways(&f, bar)?);
+		}
+		Ok(result)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use osmpbfreader::{Node, NodeId, OsmId, Ref, Relation, RelationId, Tags, WayId};
+
+	use super::*;
+	use crate::locations_on_ways;
+
+	fn tags(pairs: &[(&str, &str)]) -> Tags {
+		let mut tags = Tags::new();
+		for (k, v) in pairs {
+			tags.insert((*k).into(), (*v).into());
+		}
+		tags
+	}
+
+	fn node(id: i64, lat: i32, lon: i32, pairs: &[(&str, &str)]) -> Node {
+		Node { id: NodeId(id), tags: tags(pairs), decimicro_lat: lat, decimicro_lon: lon }
+	}
+
+	fn way(id: i64, nodes: &[i64], pairs: &[(&str, &str)]) -> Way {
+		Way { id: WayId(id), tags: tags(pairs), nodes: nodes.iter().map(|n| NodeId(*n)).collect() }
+	}
+
+	fn relation(id: i64, refs: &[(OsmId, &str)], pairs: &[(&str, &str)]) -> Relation {
+		Relation { id: RelationId(id), tags: tags(pairs), refs: refs.iter().map(|(member, role)| Ref { member: *member, role: (*role).into() }).collect() }
+	}
+
+	fn temp_path(name: &str) -> std::path::PathBuf {
+		std::env::temp_dir().join(format!("rs-osm-{name}-{}.osm.pbf", std::process::id()))
+	}
+
+	fn ids(index: &BlobIndex, kind: Kind) -> Vec<i64> {
+		index.filter_map(kind, |obj, _| Some(match obj {
+			OsmObj::Node(node) => node.id.0,
+			OsmObj::Way(way) => way.id.0,
+			OsmObj::Relation(relation) => relation.id.0,
+		}), &ProgressBar::hidden()).unwrap()
+	}
+
+	#[test]
+	fn blob_header_reads_type_and_datasize() {
+		let mut header = osmpbfreader::fileformat::BlobHeader::new();
+		header.set_field_type("OSMData".to_string());
+		header.set_indexdata(vec![1, 2, 3]);
+		header.set_datasize(300);
+		let data = protobuf::Message::write_to_bytes(&header).unwrap();
+		assert_eq!(blob_header(&data).unwrap(), ("OSMData".to_string(), 300));
+		assert!(blob_header(&data[..data.len() - 1]).is_err());
+		assert!(blob_header(&data[..4]).is_err());
+	}
+
+	#[test]
+	fn sorted_blocks_are_classified_by_binary_search() {
+		let path = temp_path("sorted");
+		let mut writer = locations_on_ways::PbfWriter::create(&path, &["Sort.Type_then_ID"]).unwrap();
+		writer.block(&[node(1, 0, 0, &[]), node(2, 0, 0, &[])], &[], &[]).unwrap();
+		writer.block(&[node(3, 0, 0, &[])], &[], &[]).unwrap();
+		writer.block(&[node(4, 0, 0, &[])], &[(way(10, &[1, 2], &[]), vec![])], &[]).unwrap();
+		writer.block(&[], &[(way(11, &[3, 4], &[]), vec![])], &[]).unwrap();
+		writer.block(&[], &[(way(12, &[1, 4], &[]), vec![])], &[relation(20, &[(OsmId::Way(WayId(10)), "outer")], &[])]).unwrap();
+		writer.block(&[], &[], &[relation(21, &[], &[]), relation(22, &[], &[])]).unwrap();
+		writer.finish().unwrap();
+
+		let index = BlobIndex::build(&path).unwrap();
+		let kinds = index.blobs.iter().map(|blob| blob.kinds.load(Ordering::Relaxed)).collect::<Vec<_>>();
+		let counts = [Kind::Node, Kind::Way, Kind::Relation].map(|kind| index.blocks(kind));
+		let elements = [Kind::Node, Kind::Way, Kind::Relation].map(|kind| ids(&index, kind));
+		std::fs::remove_file(&path).unwrap();
+
+		assert_eq!(kinds, vec![NODES, NODES, NODES | WAYS, WAYS, WAYS | RELATIONS, RELATIONS]);
+		assert_eq!(counts, [3, 3, 2]);
+		assert_eq!(elements, [vec![1, 2, 3, 4], vec![10, 11, 12], vec![20, 21, 22]]);
+	}
+
+	#[test]
+	fn mixed_blocks_are_learned_by_the_first_pass() {
+		let path = temp_path("mixed");
+		let mut writer = locations_on_ways::PbfWriter::create(&path, &[]).unwrap();
+		writer.block(&[node(1, 0, 0, &[])], &[(way(10, &[1, 2], &[]), vec![])], &[relation(20, &[], &[])]).unwrap();
+		writer.block(&[node(2, 0, 0, &[])], &[], &[]).unwrap();
+		writer.block(&[], &[(way(11, &[2, 3], &[]), vec![])], &[relation(21, &[], &[])]).unwrap();
+		writer.block(&[node(3, 0, 0, &[])], &[], &[relation(22, &[], &[])]).unwrap();
+		writer.finish().unwrap();
+
+		let index = BlobIndex::build(&path).unwrap();
+		let unknown = [Kind::Node, Kind::Way, Kind::Relation].map(|kind| index.blocks(kind));
+		let nodes = ids(&index, Kind::Node);
+		let learned = [Kind::Node, Kind::Way, Kind::Relation].map(|kind| index.blocks(kind));
+		let ways = ids(&index, Kind::Way);
+		let relations = ids(&index, Kind::Relation);
+		let located = index.located_ways(|way, _, _| Some(way.id.0), &ProgressBar::hidden());
+		std::fs::remove_file(&path).unwrap();
+
+		assert_eq!(unknown, [4, 4, 4]);
+		assert_eq!(learned, [3, 2, 3]);
+		assert_eq!((nodes, ways, relations), (vec![1, 2, 3], vec![10, 11], vec![20, 21, 22]));
+		assert!(located.is_err(), "ways without locations");
+	}
+
+	#[test]
+	fn locations_on_ways_round_trip() {
+		let path = temp_path("low");
+		let nodes = [
+			node(1, 515_000_000, -1_250_000, &[("place", "city"), ("name", "Somewhere")]),
+			node(5, -338_688_000, 1_512_093_000, &[("place", "town")]),
+		];
+		let ways = [
+			(way(10, &[2, 3, 4, 2], &[("boundary", "administrative"), ("admin_level", "8")]), vec![(1, 2), (-900_000_000, -1_800_000_000), (900_000_000, 1_799_999_999), (1, 2)]),
+			(way(11, &[4, 6], &[]), vec![(900_000_000, 1_799_999_999), (-3, 0)]),
+		];
+		let relations = [
+			relation(20, &[(OsmId::Way(WayId(10)), "outer"), (OsmId::Node(NodeId(1)), "admin_centre"), (OsmId::Relation(RelationId(21)), "subarea")], &[("type", "boundary")]),
+			relation(21, &[(OsmId::Way(WayId(11)), "")], &[]),
+		];
+		locations_on_ways::write(&path, &nodes, &ways, &relations).unwrap();
+
+		let features = optional_features(&path).unwrap();
+		let index = BlobIndex::build(&path).unwrap();
+		let located = index.located_ways(|way, locations, _| Some((way, locations)), &ProgressBar::hidden()).unwrap();
+		let objs = OsmPbfReader::new(File::open(&path).unwrap()).iter().collect::<Result<Vec<_>, _>>().unwrap();
+		std::fs::remove_file(&path).unwrap();
+
+		assert!(features.iter().any(|f| f == "LocationsOnWays"));
+		assert!(features.iter().any(|f| f == "Sort.Type_then_ID"));
+		assert_eq!(located, ways);
+		let expected = nodes.into_iter().map(OsmObj::Node)
+			.chain(ways.into_iter().map(|(way, _)| OsmObj::Way(way)))
+			.chain(relations.into_iter().map(OsmObj::Relation))
+			.collect::<Vec<_>>();
+		assert_eq!(objs, expected);
+	}
+
+	#[test]
+	fn metadata_survives_a_round_trip_through_osm_io() {
+		use osm_io::osm::pbf::{compression_type::CompressionType, file_info::FileInfo, writer::Writer};
+
+		let path = temp_path("meta");
+		let meta = |version, user: &str| Meta { version, timestamp: 1_600_000_000_000 + version as i64 * 1000, changeset: 40 + version as i64, uid: version * 7, user: user.into(), visible: true };
+		let elements = [
+			(OsmObj::Node(node(1, 10, 20, &[("place", "city")])), meta(3, "alice")),
+			(OsmObj::Node(node(2, 30, 40, &[])), meta(1, "bob")),
+			(OsmObj::Node(node(5, 50, 60, &[])), meta(2, "alice")),
+			(OsmObj::Way(way(10, &[1, 2, 5], &[("boundary", "administrative")])), meta(4, "carol")),
+			(OsmObj::Relation(relation(20, &[(OsmId::Way(WayId(10)), "outer")], &[("type", "boundary")])), meta(9, "bob")),
+		];
+		let mut writer = Writer::from_file_info(path.clone(), FileInfo::default(), CompressionType::Zlib).unwrap();
+		writer.write_header().unwrap();
+		for (obj, meta) in elements.iter().cloned() {
+			writer.write_element(crate::input::to_osm_io(obj, meta)).unwrap();
+		}
+		writer.close().unwrap();
+
+		let index = BlobIndex::build(&path).unwrap();
+		let read = [Kind::Node, Kind::Way, Kind::Relation]
+			.map(|kind| index.filter_map(kind, |obj, info| Some((obj, info.meta())), &ProgressBar::hidden()).unwrap())
+			.concat();
+		std::fs::remove_file(&path).unwrap();
+
+		assert_eq!(read, elements);
+	}
+}
@@ -0,0 +1,215 @@
+use std::{
+	collections::{HashMap, HashSet},
+	fs::{File, OpenOptions},
+	io::{BufWriter, Write},
+	path::{Path, PathBuf},
+	sync::atomic::{AtomicI64, AtomicU64, Ordering},
+};
+
+use memmap2::{Mmap, MmapMut};
+use indicatif::ProgressBar;
+use osmpbfreader::{Node, NodeId, OsmObj, Tags};
+use rayon::slice::ParallelSliceMut;
+
+use super::{blobs::BlobIndex, Source, Sources};
+
+const MAGIC: &[u8; 8] = b"RSOSMLOC";
+/// Bump on any layout change, stores of other versions are rebuilt
+const VERSION: u32 = 1;
+/// `MAGIC`, `VERSION`, layout and the first id of the dense layout
+const HEADER: usize = 24;
+/// Stored latitudes are shifted by it, so a zeroed slot of the dense layout is a missing node
+const LAT_SHIFT: i32 = 1_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Layout {
+	/// `(lat, lon)` at `id - first_id`, when most ids of the range are present
+	Dense,
+	/// `(id, lat, lon)` sorted by id
+	Sparse,
+}
+
+/// Decimicro locations of every node of one file, memory mapped from `<file>.locations`
+pub struct LocationStore {
+	map: Mmap,
+	layout: Layout,
+	first_id: i64,
+}
+
+fn store_path(source: &Source) -> PathBuf {
+	let mut path = source.path.clone().into_os_string();
+	path.push(".locations");
+	path.into()
+}
+
+fn header(layout: Layout, first_id: i64) -> [u8; HEADER] {
+	let mut header = [0u8; HEADER];
+	header[..8].copy_from_slice(MAGIC);
+	header[8..12].copy_from_slice(&VERSION.to_le_bytes());
+	header[12..16].copy_from_slice(&(layout as u32).to_le_bytes());
+	header[16..].copy_from_slice(&first_id.to_le_bytes());
+	header
+}
+
+/// `lat` in the low half, both little endian
+fn pack(node: &Node) -> u64 {
+	((node.decimicro_lat + LAT_SHIFT) as u32 as u64) | ((node.decimicro_lon as u32 as u64) << 32)
+}
+
+impl LocationStore {
+	/// Store next to `source`, built when it's missing, older than the source or of another version
+	pub fn open_or_build(source: &Source) -> anyhow::Result<Self> {
+		let path = store_path(source);
+		let modified = |path: &Path| std::fs::metadata(path).and_then(|m| m.modified()).ok();
+		let fresh = modified(&path).is_some_and(|store| modified(&source.path).is_some_and(|src| store >= src));
+		if fresh {
+			match Self::open(&path) {
+				Ok(store) => return Ok(store),
+				Err(e) => println!("rebuilding {}: {e}", path.display()),
+			}
+		}
+		Self::build(source, &path)?;
+		Self::open(&path)
+	}
+
+	fn open(path: &Path) -> anyhow::Result<Self> {
+		let map = unsafe { Mmap::map(&File::open(path)?)? };
+		if map.len() < HEADER || &map[..8] != MAGIC {
+			anyhow::bail!("not a node locations store");
+		}
+		let version = u32::from_le_bytes(map[8..12].try_into()?);
+		if version != VERSION {
+			anyhow::bail!("store has version {version}, this build reads {VERSION}");
+		}
+		let layout = match u32::from_le_bytes(map[12..16].try_into()?) {
+			0 => Layout::Dense,
+			1 => Layout::Sparse,
+			other => anyhow::bail!("unknown layout {other}"),
+		};
+		let first_id = i64::from_le_bytes(map[16..24].try_into()?);
+		Ok(Self { map, layout, first_id })
+	}
+
+	/// Dense layout costs 8 bytes per id of the range, sparse one 16 bytes per node, so dense wins when at least half of the range is present
+	fn build(source: &Source, path: &Path) -> anyhow::Result<()> {
+		println!("building node locations of {}", source.path.display());
+		let (min_id, max_id) = (AtomicI64::new(i64::MAX), AtomicI64::new(i64::MIN));
+		let count = source.filter_map(|obj| {
+			let node = obj.node()?;
+			min_id.fetch_min(node.id.0, Ordering::Relaxed);
+			max_id.fetch_max(node.id.0, Ordering::Relaxed);
+			Some(())
+		})?.len() as u64;
+		let (min_id, max_id) = (min_id.into_inner(), max_id.into_inner());
+		let span = if count == 0 { 0 } else { max_id.abs_diff(min_id) + 1 };
+		let layout = match count > 0 && span <= count * 2 {
+			true => Layout::Dense,
+			false => Layout::Sparse,
+		};
+
+		let mut tmp = path.as_os_str().to_owned();
+		tmp.push(".tmp");
+		let tmp = PathBuf::from(tmp);
+		let file = OpenOptions::new().read(true).write(true).create(true).truncate(true).open(&tmp)?;
+		match layout {
+			Layout::Dense => {
+				file.set_len(HEADER as u64 + span * 8)?;
+				let mut map = unsafe { MmapMut::map_mut(&file)? };
+				map[..HEADER].copy_from_slice(&header(layout, min_id));
+				let slots = &mut map[HEADER..];
+				// mapping is page aligned and `HEADER` is a multiple of 8, every node has a slot of its own
+				let slots = unsafe { std::slice::from_raw_parts(slots.as_mut_ptr() as *const AtomicU64, slots.len() / 8) };
+				source.filter_map(|obj| {
+					let node = obj.node()?;
+					slots[(node.id.0 - min_id) as usize].store(pack(node).to_le(), Ordering::Relaxed);
+					None::<()>
+				})?;
+				map.flush()?;
+			},
+			Layout::Sparse => {
+				let mut entries = source.filter_map(|obj| obj.node().map(|node| (node.id.0, pack(node))))?;
+				entries.par_sort_unstable_by_key(|(id, _)| *id);
+				entries.dedup_by_key(|(id, _)| *id);
+				let mut out = BufWriter::new(file);
+				out.write_all(&header(layout, 0))?;
+				for (id, location) in entries {
+					out.write_all(&id.to_le_bytes())?;
+					out.write_all(&location.to_le_bytes())?;
+				}
+				out.flush()?;
+			},
+		}
+		std::fs::rename(&tmp, path)?;
+		println!("{count} node locations written to {} ({layout:?})", path.display());
+		Ok(())
+	}
+
+	/// Decimicro `(lat, lon)`
+	pub fn get(&self, id: NodeId) -> Option<(i32, i32)> {
+		let data = &self.map[HEADER..];
+		let entry = match self.layout {
+			Layout::Dense => {
+				let i = usize::try_from(id.0.checked_sub(self.first_id)?).ok()?;
+				data.get(i * 8..i * 8 + 8)?
+			},
+			Layout::Sparse => {
+				let id_at = |i: usize| i64::from_le_bytes(data[i * 16..i * 16 + 8].try_into().unwrap());
+				let (mut low, mut high) = (0, data.len() / 16);
+				while low < high {
+					let mid = (low + high) / 2;
+					if id_at(mid) < id.0 { low = mid + 1 } else { high = mid }
+				}
+				if low == data.len() / 16 || id_at(low) != id.0 {
+					return None;
+				}
+				&data[low * 16 + 8..low * 16 + 16]
+			},
+		};
+		let lat = i32::from_le_bytes(entry[..4].try_into().unwrap());
+		if lat == 0 {
+			return None;
+		}
+		Some((lat - LAT_SHIFT, i32::from_le_bytes(entry[4..].try_into().unwrap())))
+	}
+}
+
+enum FileLocations {
+	Store(LocationStore),
+	/// Nodes of needed ways of a `LocationsOnWays` file, taken from the ways
+	OnWays(HashMap<NodeId, (i32, i32)>),
+}
+
+/// Node locations of all inputs, the first file having a node wins
+pub struct Locations(Vec<FileLocations>);
+
+impl Locations {
+	pub fn get(&self, id: NodeId) -> Option<(i32, i32)> {
+		self.0.iter().find_map(|file| match file {
+			FileLocations::Store(store) => store.get(id),
+			FileLocations::OnWays(nodes) => nodes.get(&id).copied(),
+		})
+	}
+
+	/// Untagged node, enough for geometry
+	pub fn node(&self, id: NodeId) -> Option<Node> {
+		let (decimicro_lat, decimicro_lon) = self.get(id)?;
+		Some(Node { id, tags: Tags::new(), decimicro_lat, decimicro_lon })
+	}
+}
+
+impl Sources {
+	/// Stores of every file, see `LocationStore::open_or_build`. Files with `LocationsOnWays` need no store and no node pass,
+	/// nodes of ways among `elements` are located by a pass over way blocks
+	pub fn locations(&self, elements: &[OsmObj]) -> anyhow::Result<Locations> {
+		let way_ids = elements.iter().filter_map(|obj| obj.way()).map(|way| way.id).collect::<HashSet<_>>();
+		let files = self.0.iter().map(|source| Ok(match source.locations_on_ways()? {
+			true => {
+				println!("taking node locations from ways of {}", source.path.display());
+				let ways = BlobIndex::build(&source.path)?.located_ways(|way, locations, _| way_ids.contains(&way.id).then(|| way.nodes.into_iter().zip(locations)), &ProgressBar::hidden())?;
+				FileLocations::OnWays(ways.into_iter().flatten().collect())
+			},
+			false => FileLocations::Store(LocationStore::open_or_build(source)?),
+		})).collect::<anyhow::Result<_>>()?;
+		Ok(Locations(files))
+	}
+}
@@ -0,0 +1,268 @@
+mod blobs;
+mod locations;
+mod o5m;
+mod osc;
+mod overpass;
+
+use std::{
+	collections::HashSet,
+	fs::File,
+	io::{BufRead, BufReader, Read},
+	path::{Path, PathBuf},
+};
+
+use osmpbfreader::{OsmObj, OsmPbfReader};
+use serde::Serialize;
+
+pub use self::{
+	blobs::Kind,
+	locations::Locations,
+	o5m::O5mReader,
+	osc::{read_changes, Action},
+	overpass::OverpassReader,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Format {
+	Pbf,
+	/// OSM XML, Overpass `out geom` included
+	Xml,
+	O5m,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Compression {
+	None,
+	Gzip,
+	Bzip2,
+}
+
+impl Compression {
+	/// By magic bytes
+	pub fn of(path: &Path) -> anyhow::Result<Self> {
+		let mut head = [0u8; 3];
+		let read = read_head(File::open(path).map_err(|e| anyhow::anyhow!("can't open {}: {e}", path.display()))?, &mut head)?;
+		Ok(match &head[..read] {
+			[0x1f, 0x8b, ..] => Compression::Gzip,
+			[b'B', b'Z', b'h'] => Compression::Bzip2,
+			_ => Compression::None,
+		})
+	}
+
+	/// Decompressed contents of `path`
+	pub fn reader(&self, path: &Path) -> anyhow::Result<Box<dyn BufRead + Send>> {
+		let file = BufReader::new(File::open(path)?);
+		Ok(match self {
+			Compression::None => Box::new(file),
+			Compression::Gzip => Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(file))),
+			Compression::Bzip2 => Box::new(BufReader::new(bzip2::bufread::MultiBzDecoder::new(file))),
+		})
+	}
+}
+
+/// OSM file of any supported format, detected by extension or by magic bytes when the extension says nothing
+pub struct Source {
+	pub path: PathBuf,
+	pub format: Format,
+	pub compression: Compression,
+}
+
+fn format_of_extension(extension: &str) -> Option<Format> {
+	match extension {
+		"pbf" => Some(Format::Pbf),
+		"osm" | "xml" => Some(Format::Xml),
+		"o5m" | "o5c" => Some(Format::O5m),
+		_ => None,
+	}
+}
+
+fn format_of_head(head: &[u8]) -> Option<Format> {
+	let text = head.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(head);
+	let text = &text[text.iter().position(|b| !b.is_ascii_whitespace()).unwrap_or(text.len())..];
+	if text.starts_with(b"<?xml") || text.starts_with(b"<osm") {
+		return Some(Format::Xml);
+	}
+	// reset followed by the `o5m2` header dataset
+	if head.starts_with(b"\xff\xe0\x04o5m2") || head.starts_with(b"\xff\xe0\x04o5c2") {
+		return Some(Format::O5m);
+	}
+	// size of the first BlobHeader and its `type` field
+	if head.len() > 15 && &head[4..15] == b"\x0a\x09OSMHeader" {
+		return Some(Format::Pbf);
+	}
+	None
+}
+
+impl Source {
+	pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+		let path = path.as_ref().to_path_buf();
+		let compression = Compression::of(&path)?;
+		let mut head = [0u8; 64];
+		let mut source = Self { path, format: Format::Pbf, compression };
+
+		// `planet.osm.bz2` is named by the inner file
+		let mut name = source.path.clone();
+		if compression != Compression::None && name.extension().is_some_and(|e| ["gz", "bz2"].contains(&e.to_string_lossy().as_ref())) {
+			name.set_extension("");
+		}
+		source.format = match name.extension().and_then(|e| format_of_extension(&e.to_string_lossy())) {
+			Some(format) => format,
+			None => {
+				let read = read_head(source.reader()?, &mut head)?;
+				format_of_head(&head[..read]).ok_or_else(|| anyhow::anyhow!("unknown format of {}", source.path.display()))?
+			},
+		};
+		if source.format == Format::Pbf && compression != Compression::None {
+			anyhow::bail!("{} is compressed PBF, decompress it first", source.path.display());
+		}
+		Ok(source)
+	}
+
+	fn reader(&self) -> anyhow::Result<Box<dyn BufRead + Send>> {
+		self.compression.reader(&self.path)
+	}
+
+	/// Passes every element through `f` in one read of the file. PBF is decoded in parallel, other formats sequentially
+	pub fn filter_map<T: Send>(&self, f: impl Fn(OsmObj) -> Option<T> + Sync + Send) -> anyhow::Result<Vec<T>> {
+		match self.format {
+			Format::Pbf => OsmPbfReader::new(File::open(&self.path)?)
+				.par_iter()
+				.filter_map(|obj| obj.map(&f).transpose())
+				.collect::<Result<Vec<_>, _>>()
+				.map_err(Into::into),
+			Format::Xml => OverpassReader::new(self.reader()?)
+				.filter_map(|obj| obj.map(&f).transpose())
+				.collect(),
+			Format::O5m => O5mReader::new(self.reader()?)
+				.filter_map(|obj| obj.map(&f).transpose())
+				.collect(),
+		}
+	}
+
+	/// All elements in memory
+	pub fn elements(&self) -> anyhow::Result<Vec<OsmObj>> {
+		self.filter_map(Some)
+	}
+}
+
+/// Several files read as one, like neighbouring regional extracts sharing border ways and nodes
+pub struct Sources(pub Vec<Source>);
+
+impl Sources {
+	/// Comma separated paths
+	pub fn open(paths: &str) -> anyhow::Result<Self> {
+		let sources = paths.split(',').map(str::trim).filter(|p| !p.is_empty()).map(Source::open).collect::<anyhow::Result<Vec<_>>>()?;
+		if sources.is_empty() {
+			anyhow::bail!("no input files in {paths:?}");
+		}
+		Ok(Self(sources))
+	}
+
+	/// `Source::filter_map` of every file one after another, elements shared by files are passed more than once
+	pub fn filter_map<T: Send>(&self, f: impl Fn(OsmObj) -> Option<T> + Sync + Send) -> anyhow::Result<Vec<T>> {
+		let mut result = Vec::new();
+		for source in self.0.iter() {
+			result.append(&mut source.filter_map(&f)?);
+		}
+		Ok(result)
+	}
+
+	/// Elements passing `seed` with ways and member nodes they refer to, each id once. Instead of holding the whole file it's read in passes like `--cache` does:
+	/// seed elements, then member ways of seed relations, then member nodes of seed relations.
+	/// Nodes of ways aren't read, they are in `locations`. Members of member relations aren't followed
+	pub fn referenced(&self, seed: impl Fn(&OsmObj) -> bool + Sync + Send) -> anyhow::Result<Vec<OsmObj>> {
+		println!("reading seed elements");
+		let mut elements = self.filter_map(|obj| seed(&obj).then_some(obj))?;
+		let seed_ids = elements.iter().map(|obj| obj.id()).collect::<HashSet<_>>();
+
+		let member_way_ids = elements.iter()
+			.filter_map(|obj| obj.relation())
+			.flat_map(|rel| rel.refs.iter().filter_map(|rf| rf.member.way()))
+			.filter(|way_id| !seed_ids.contains(&(*way_id).into()))
+			.collect::<HashSet<_>>();
+		println!("reading {} member ways", member_way_ids.len());
+		let mut ways = self.filter_map(|obj| obj.way().is_some_and(|way| member_way_ids.contains(&way.id)).then_some(obj))?;
+		drop(member_way_ids);
+		elements.append(&mut ways);
+
+		let node_ids = elements.iter()
+			.filter_map(|obj| obj.relation())
+			.flat_map(|rel| rel.refs.iter().filter_map(|rf| rf.member.node()))
+			.filter(|node_id| !seed_ids.contains(&(*node_id).into()))
+			.collect::<HashSet<_>>();
+		println!("reading {} member nodes", node_ids.len());
+		let mut nodes = self.filter_map(|obj| obj.node().is_some_and(|node| node_ids.contains(&node.id)).then_some(obj))?;
+		elements.append(&mut nodes);
+
+		Ok(self.dedup(elements))
+	}
+
+	/// Extracts cut from the same planet have equal copies of border elements, the first file wins
+	fn dedup(&self, mut elements: Vec<OsmObj>) -> Vec<OsmObj> {
+		if self.0.len() > 1 {
+			let mut seen = HashSet::with_capacity(elements.len());
+			let before = elements.len();
+			elements.retain(|obj| seen.insert(obj.id()));
+			println!("merged {} files, {} duplicate elements dropped", self.0.len(), before - elements.len());
+		}
+		elements
+	}
+}
+
+fn read_head(mut input: impl Read, head: &mut [u8]) -> anyhow::Result<usize> {
+	let mut read = 0;
+	while read < head.len() {
+		match input.read(&mut head[read..])? {
+			0 => break,
+			n => read += n,
+		}
+	}
+	Ok(read)
+}
+
+/// Version, timestamp in milliseconds, changeset and author of an element, as `osm_io` writers take them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Meta {
+	pub version: i32,
+	pub timestamp: i64,
+	pub changeset: i64,
+	pub uid: i32,
+	pub user: String,
+	pub visible: bool,
+}
+
+impl Default for Meta {
+	/// Of an element read without metadata: version 1 without author
+	fn default() -> Self {
+		Self { version: 1, timestamp: 0, changeset: 0, uid: 0, user: String::new(), visible: true }
+	}
+}
+
+/// `osm_io` element for PBF writers, osmpbfreader objects have no metadata so it comes separately
+pub fn to_osm_io(obj: OsmObj, meta: Meta) -> osm_io::osm::model::element::Element {
+	use osm_io::osm::model::{coordinate::Coordinate, element::Element, node, relation, tag::Tag, way};
+
+	let Meta { version, timestamp, changeset, uid, user, visible } = meta;
+	let tags = |tags: &osmpbfreader::Tags| tags.iter().map(|(k, v)| Tag::new(k.to_string(), v.to_string())).collect::<Vec<_>>();
+	match obj {
+		OsmObj::Node(n) => Element::Node {
+			node: node::Node::new(n.id.0, version, Coordinate::new(n.lat(), n.lon()), timestamp, changeset, uid, user, visible, tags(&n.tags)),
+		},
+		OsmObj::Way(w) => Element::Way {
+			way: way::Way::new(w.id.0, version, timestamp, changeset, uid, user, visible, w.nodes.iter().map(|n| n.0).collect(), tags(&w.tags)),
+		},
+		OsmObj::Relation(r) => {
+			let members = r.refs.iter().map(|rf| {
+				let data = relation::MemberData::new(rf.member.inner_id(), rf.role.to_string());
+				match rf.member {
+					osmpbfreader::OsmId::Node(_) => relation::Member::Node { member: data },
+					osmpbfreader::OsmId::Way(_) => relation::Member::Way { member: data },
+					osmpbfreader::OsmId::Relation(_) => relation::Member::Relation { member: data },
+				}
+			}).collect();
+			Element::Relation { relation: relation::Relation::new(r.id.0, version, timestamp, changeset, uid, user, visible, members, tags(&r.tags)) }
+		},
+	}
+}
@@ -0,0 +1,438 @@
+// https://wiki.openstreetmap.org/wiki/O5m
+
+use std::{
+	collections::VecDeque,
+	io::{BufRead, ErrorKind},
+};
+
+use osmpbfreader::{Node, NodeId, OsmId, OsmObj, Ref, Relation, RelationId, Tags, Way, WayId};
+
+const NODE: u8 = 0x10;
+const WAY: u8 = 0x11;
+const RELATION: u8 = 0x12;
+const RESET: u8 = 0xff;
+const END: u8 = 0xfe;
+
+const STRING_TABLE_SIZE: usize = 15_000;
+/// longer strings (both of a pair together) are never referenced
+const MAX_TABLE_STRING: usize = 250;
+
+/// Delta coded values, all of them start from zero after every reset
+#[derive(Default)]
+struct Deltas {
+	id: [i64; 3],
+	lat: i64,
+	lon: i64,
+	timestamp: i64,
+	changeset: i64,
+	/// node, way and relation references, node references of ways share the first one
+	refs: [i64; 3],
+}
+
+/// Streams objects of an O5M file (`.o5c` change files are read as plain data, deletions are skipped)
+pub struct O5mReader<R: BufRead> {
+	input: R,
+	deltas: Deltas,
+	/// most recent last
+	strings: VecDeque<(String, String)>,
+	dataset: Vec<u8>,
+	done: bool,
+}
+
+struct Cursor<'a> {
+	data: &'a [u8],
+	pos: usize,
+}
+
+impl Cursor<'_> {
+	fn is_empty(&self) -> bool {
+		self.pos >= self.data.len()
+	}
+
+	fn varint(&mut self) -> anyhow::Result<u64> {
+		let mut value = 0u64;
+		for shift in (0..64).step_by(7) {
+			let byte = *self.data.get(self.pos).ok_or_else(|| anyhow::anyhow!("o5m dataset ends inside a number"))?;
+			self.pos += 1;
+			value |= ((byte & 0x7f) as u64) << shift;
+			if byte & 0x80 == 0 {
+				return Ok(value);
+			}
+		}
+		anyhow::bail!("o5m number is too long")
+	}
+
+	/// Least significant bit is the sign
+	fn signed(&mut self) -> anyhow::Result<i64> {
+		let value = self.varint()?;
+		Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+	}
+
+	fn delta(&mut self, last: &mut i64) -> anyhow::Result<i64> {
+		*last += self.signed()?;
+		Ok(*last)
+	}
+
+	fn zero_terminated(&mut self) -> anyhow::Result<&[u8]> {
+		let rest = &self.data[self.pos..];
+		let len = rest.iter().position(|b| *b == 0).ok_or_else(|| anyhow::anyhow!("o5m string without terminator"))?;
+		self.pos += len + 1;
+		Ok(&rest[..len])
+	}
+}
+
+impl<R: BufRead> O5mReader<R> {
+	pub fn new(input: R) -> Self {
+		Self { input, deltas: Deltas::default(), strings: VecDeque::new(), dataset: Vec::new(), done: false }
+	}
+
+	/// Inline string (pair) or a reference to one of the previous ones
+	fn string(&mut self, cursor: &mut Cursor, pair: bool) -> anyhow::Result<(String, String)> {
+		let reference = cursor.varint()? as usize;
+		if reference != 0 {
+			return self.strings.len().checked_sub(reference)
+				.map(|i| self.strings[i].clone())
+				.ok_or_else(|| anyhow::anyhow!("o5m string reference {reference} is out of table"));
+		}
+		let first = String::from_utf8_lossy(cursor.zero_terminated()?).into_owned();
+		let second = match pair {
+			true => String::from_utf8_lossy(cursor.zero_terminated()?).into_owned(),
+			false => String::new(),
+		};
+		if first.len() + second.len() <= MAX_TABLE_STRING {
+			if self.strings.len() == STRING_TABLE_SIZE {
+				self.strings.pop_front();
+			}
+			self.strings.push_back((first.clone(), second.clone()));
+		}
+		Ok((first, second))
+	}
+
+	/// Skips version, timestamp, changeset and author
+	fn skip_info(&mut self, cursor: &mut Cursor) -> anyhow::Result<()> {
+		let version = cursor.varint()?;
+		if version == 0 {
+			return Ok(());
+		}
+		if cursor.delta(&mut self.deltas.timestamp)? != 0 {
+			cursor.delta(&mut self.deltas.changeset)?;
+			self.string(cursor, true)?;
+		}
+		Ok(())
+	}
+
+	fn tags(&mut self, cursor: &mut Cursor) -> anyhow::Result<Tags> {
+		let mut tags = Tags::new();
+		while !cursor.is_empty() {
+			let (k, v) = self.string(cursor, true)?;
+			tags.insert(k.into(), v.into());
+		}
+		Ok(tags)
+	}
+
+	fn parse(&mut self, kind: u8, data: &[u8]) -> anyhow::Result<Option<OsmObj>> {
+		let mut cursor = Cursor { data, pos: 0 };
+		let id = match kind {
+			NODE => cursor.delta(&mut self.deltas.id[0])?,
+			WAY => cursor.delta(&mut self.deltas.id[1])?,
+			_ => cursor.delta(&mut self.deltas.id[2])?,
+		};
+		// deleted objects are the id, optionally with version and author, and nothing else
+		if cursor.is_empty() {
+			return Ok(None);
+		}
+		self.skip_info(&mut cursor)?;
+		if cursor.is_empty() {
+			return Ok(None);
+		}
+
+		let obj = match kind {
+			NODE => {
+				let lon = cursor.delta(&mut self.deltas.lon)?;
+				let lat = cursor.delta(&mut self.deltas.lat)?;
+				OsmObj::Node(Node {
+					id: NodeId(id),
+					decimicro_lat: lat as i32,
+					decimicro_lon: lon as i32,
+					tags: self.tags(&mut cursor)?,
+				})
+			},
+			WAY => {
+				let refs_end = cursor.varint()? as usize + cursor.pos;
+				let mut nodes = Vec::new();
+				while cursor.pos < refs_end {
+					nodes.push(NodeId(cursor.delta(&mut self.deltas.refs[0])?));
+				}
+				OsmObj::Way(Way { id: WayId(id), nodes, tags: self.tags(&mut cursor)? })
+			},
+			_ => {
+				let refs_end = cursor.varint()? as usize + cursor.pos;
+				let mut refs = Vec::new();
+				while cursor.pos < refs_end {
+					let signed = cursor.signed()?;
+					let (type_role, _) = self.string(&mut cursor, false)?;
+					let (member_type, role) = type_role.split_at(type_role.len().min(1));
+					let member = match member_type {
+						"0" => OsmId::Node(NodeId({ self.deltas.refs[0] += signed; self.deltas.refs[0] })),
+						"1" => OsmId::Way(WayId({ self.deltas.refs[1] += signed; self.deltas.refs[1] })),
+						"2" => OsmId::Relation(RelationId({ self.deltas.refs[2] += signed; self.deltas.refs[2] })),
+						_ => anyhow::bail!("o5m relation {id} has member of unknown type {member_type:?}"),
+					};
+					refs.push(Ref { member, role: role.into() });
+				}
+				OsmObj::Relation(Relation { id: RelationId(id), refs, tags: self.tags(&mut cursor)? })
+			},
+		};
+		Ok(Some(obj))
+	}
+
+	fn read_byte(&mut self) -> anyhow::Result<Option<u8>> {
+		let mut byte = [0u8];
+		match self.input.read_exact(&mut byte) {
+			Ok(()) => Ok(Some(byte[0])),
+			Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(None),
+			Err(e) => Err(e.into()),
+		}
+	}
+
+	fn read_varint(&mut self) -> anyhow::Result<u64> {
+		let mut value = 0u64;
+		for shift in (0..64).step_by(7) {
+			let byte = self.read_byte()?.ok_or_else(|| anyhow::anyhow!("o5m file ends inside a dataset length"))?;
+			value |= ((byte & 0x7f) as u64) << shift;
+			if byte & 0x80 == 0 {
+				return Ok(value);
+			}
+		}
+		anyhow::bail!("o5m dataset length is too long")
+	}
+
+	fn next_object(&mut self) -> anyhow::Result<Option<OsmObj>> {
+		loop {
+			let Some(kind) = self.read_byte()? else { return Ok(None) };
+			match kind {
+				RESET => {
+					self.deltas = Deltas::default();
+					self.strings.clear();
+				},
+				END => return Ok(None),
+				// single byte datasets
+				0xf0..=0xfd => {},
+				_ => {
+					let len = self.read_varint()? as usize;
+					let mut dataset = std::mem::take(&mut self.dataset);
+					dataset.resize(len, 0);
+					self.input.read_exact(&mut dataset)?;
+					let obj = match kind {
+						NODE | WAY | RELATION => self.parse(kind, &dataset)?,
+						// header, bbox, timestamp and unknown datasets
+						_ => None,
+					};
+					self.dataset = dataset;
+					if obj.is_some() {
+						return Ok(obj);
+					}
+				},
+			}
+		}
+	}
+}
+
+impl<R: BufRead> Iterator for O5mReader<R> {
+	type Item = anyhow::Result<OsmObj>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		if self.done {
+			return None;
+		}
+		let next = self.next_object().transpose();
+		self.done = !matches!(next, Some(Ok(_)));
+		next
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn varint(out: &mut Vec<u8>, mut value: u64) {
+		while value >= 0x80 {
+			out.push(value as u8 | 0x80);
+			value >>= 7;
+		}
+		out.push(value as u8);
+	}
+
+	fn signed(out: &mut Vec<u8>, value: i64) {
+		varint(out, ((value << 1) ^ (value >> 63)) as u64);
+	}
+
+	fn inline(out: &mut Vec<u8>, strings: &[&str]) {
+		out.push(0);
+		for s in strings {
+			out.extend_from_slice(s.as_bytes());
+			out.push(0);
+		}
+	}
+
+	fn dataset(out: &mut Vec<u8>, kind: u8, body: Vec<u8>) {
+		out.push(kind);
+		varint(out, body.len() as u64);
+		out.extend(body);
+	}
+
+	/// Reset and `o5m2` header, as osmconvert starts files
+	fn file() -> Vec<u8> {
+		vec![RESET, 0xe0, 0x04, b'o', b'5', b'm', b'2']
+	}
+
+	/// Node without version and author
+	fn node(out: &mut Vec<u8>, id_delta: i64, lon_delta: i64, lat_delta: i64, tags: impl FnOnce(&mut Vec<u8>)) {
+		let mut body = Vec::new();
+		signed(&mut body, id_delta);
+		body.push(0);
+		signed(&mut body, lon_delta);
+		signed(&mut body, lat_delta);
+		tags(&mut body);
+		dataset(out, NODE, body);
+	}
+
+	fn read(data: &[u8]) -> anyhow::Result<Vec<OsmObj>> {
+		O5mReader::new(data).collect()
+	}
+
+	#[test]
+	fn strings_are_referenced_back_from_the_most_recent() {
+		let mut data = file();
+		node(&mut data, 1, 10, 20, |b| inline(b, &["name", "A"]));
+		node(&mut data, 1, 1, 1, |b| { inline(b, &["place", "city"]); varint(b, 2); });
+		node(&mut data, 1, 1, 1, |b| { varint(b, 1); varint(b, 2); });
+		data.push(END);
+
+		let nodes = read(&data).unwrap();
+		let tags = nodes.iter().map(|n| {
+			let mut tags = n.tags().iter().map(|(k, v)| format!("{k}={v}")).collect::<Vec<_>>();
+			tags.sort();
+			tags
+		}).collect::<Vec<_>>();
+		assert_eq!(tags, vec![vec!["name=A"], vec!["name=A", "place=city"], vec!["name=A", "place=city"]]);
+	}
+
+	#[test]
+	fn long_strings_stay_out_of_the_table() {
+		let long = "x".repeat(MAX_TABLE_STRING);
+		let mut data = file();
+		node(&mut data, 1, 0, 0, |b| inline(b, &["name", "A"]));
+		node(&mut data, 1, 0, 0, |b| inline(b, &["note", &long]));
+		// still the first pair, the long one wasn't stored
+		node(&mut data, 1, 0, 0, |b| varint(b, 1));
+
+		let nodes = read(&data).unwrap();
+		assert_eq!(nodes[1].tags().get("note").map(|v| v.len()), Some(MAX_TABLE_STRING));
+		assert_eq!(nodes[2].tags().get("name").map(|v| v.as_str()), Some("A"));
+	}
+
+	#[test]
+	fn deltas_and_strings_start_over_after_reset() {
+		let mut data = file();
+		node(&mut data, 5, 100, 200, |b| inline(b, &["name", "A"]));
+		node(&mut data, 2, -10, 5, |_| {});
+		data.push(RESET);
+		node(&mut data, 3, 7, 9, |_| {});
+		node(&mut data, 1, 0, 0, |b| varint(b, 1));
+
+		let mut reader = O5mReader::new(&data[..]);
+		let mut next = || match reader.next().unwrap().unwrap() {
+			OsmObj::Node(n) => (n.id.0, n.decimicro_lon, n.decimicro_lat),
+			other => panic!("not a node: {other:?}"),
+		};
+		assert_eq!(next(), (5, 100, 200));
+		assert_eq!(next(), (7, 90, 205));
+		assert_eq!(next(), (3, 7, 9));
+		// table was cleared together with deltas
+		assert!(reader.next().unwrap().is_err());
+		assert!(reader.next().is_none());
+	}
+
+	#[test]
+	fn ways_and_relations_keep_reference_deltas_per_member_type() {
+		let mut data = file();
+		let mut way = Vec::new();
+		signed(&mut way, 10);
+		way.push(0);
+		let mut refs = Vec::new();
+		[100, 1, -2].into_iter().for_each(|d| signed(&mut refs, d));
+		varint(&mut way, refs.len() as u64);
+		way.extend(refs);
+		inline(&mut way, &["boundary", "administrative"]);
+		dataset(&mut data, WAY, way);
+
+		let mut relation = Vec::new();
+		signed(&mut relation, 20);
+		relation.push(0);
+		let mut members = Vec::new();
+		// node refs continue from the way, ways and relations from zero
+		signed(&mut members, 5);
+		inline(&mut members, &["0admin_centre"]);
+		signed(&mut members, 10);
+		inline(&mut members, &["1outer"]);
+		signed(&mut members, 2);
+		varint(&mut members, 1);
+		signed(&mut members, 30);
+		inline(&mut members, &["2"]);
+		varint(&mut relation, members.len() as u64);
+		relation.extend(members);
+		inline(&mut relation, &["type", "boundary"]);
+		dataset(&mut data, RELATION, relation);
+
+		let elements = read(&data).unwrap();
+		let way = elements[0].way().unwrap();
+		assert_eq!((way.id.0, way.nodes.iter().map(|n| n.0).collect::<Vec<_>>()), (10, vec![100, 101, 99]));
+		assert!(way.tags.contains("boundary", "administrative"));
+
+		let relation = elements[1].relation().unwrap();
+		let members = relation.refs.iter().map(|r| (r.member, r.role.as_str())).collect::<Vec<_>>();
+		assert_eq!(relation.id.0, 20);
+		assert_eq!(members, vec![
+			(OsmId::Node(NodeId(104)), "admin_centre"),
+			(OsmId::Way(WayId(10)), "outer"),
+			(OsmId::Way(WayId(12)), "outer"),
+			(OsmId::Relation(RelationId(30)), ""),
+		]);
+		assert!(relation.tags.contains("type", "boundary"));
+	}
+
+	#[test]
+	fn deleted_objects_are_skipped() {
+		let mut data = file();
+		// deleted without version: only the id
+		let mut deleted = Vec::new();
+		signed(&mut deleted, 1);
+		dataset(&mut data, NODE, deleted);
+		// deleted with version, timestamp, changeset and author (uid 1, "user")
+		let mut deleted = Vec::new();
+		signed(&mut deleted, 1);
+		varint(&mut deleted, 3);
+		signed(&mut deleted, 1_600_000_000);
+		signed(&mut deleted, 42);
+		inline(&mut deleted, &["\u{1}", "user"]);
+		dataset(&mut data, NODE, deleted);
+		// id delta continues from the deleted ones, the author is in the table
+		let mut body = Vec::new();
+		signed(&mut body, 1);
+		varint(&mut body, 4);
+		signed(&mut body, 10);
+		signed(&mut body, 1);
+		varint(&mut body, 1);
+		signed(&mut body, 5);
+		signed(&mut body, 6);
+		dataset(&mut data, NODE, body);
+		data.push(END);
+
+		let nodes = read(&data).unwrap();
+		assert_eq!(nodes.len(), 1);
+		let node = nodes[0].node().unwrap();
+		assert_eq!((node.id.0, node.decimicro_lon, node.decimicro_lat), (3, 5, 6));
+		assert!(node.tags.is_empty());
+	}
+}
@@ -0,0 +1,104 @@
+// https://wiki.openstreetmap.org/wiki/OsmChange
+
+use std::io::BufRead;
+
+use osmpbfreader::{Node, NodeId, OsmId, OsmObj, Ref, Relation, RelationId, Tags, Way, WayId};
+use quick_xml::events::Event;
+
+use super::overpass::{attribute, attributes, decimicro};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+	Create,
+	Modify,
+	Delete,
+}
+
+/// Full new version of the object, deleted objects may have nothing but id
+pub struct Change {
+	pub action: Action,
+	pub obj: OsmObj,
+}
+
+/// Changes of an osmChange document in the document order
+pub fn read_changes(input: impl BufRead) -> anyhow::Result<Vec<Change>> {
+	let mut reader = quick_xml::Reader::from_reader(input);
+	reader.config_mut().trim_text(true);
+
+	let mut buf = Vec::new();
+	let mut changes = Vec::new();
+	let mut action = None;
+	let mut current: Option<OsmObj> = None;
+
+	loop {
+		buf.clear();
+		let event = reader.read_event_into(&mut buf)
+			.map_err(|e| anyhow::anyhow!("bad osmChange at {}: {e}", reader.error_position()))?;
+		let (e, empty) = match event {
+			Event::Start(ref e) => (e, false),
+			Event::Empty(ref e) => (e, true),
+			Event::End(ref e) => {
+				match e.local_name().as_ref() {
+					b"create" | b"modify" | b"delete" => action = None,
+					b"node" | b"way" | b"relation" => if let (Some(action), Some(obj)) = (action, current.take()) {
+						changes.push(Change { action, obj });
+					},
+					_ => {},
+				}
+				continue;
+			},
+			Event::Eof => break,
+			_ => continue,
+		};
+		let a = attributes(e)?;
+
+		match e.local_name().as_ref() {
+			b"create" => action = Some(Action::Create),
+			b"modify" => action = Some(Action::Modify),
+			b"delete" => action = Some(Action::Delete),
+			kind @ (b"node" | b"way" | b"relation") => {
+				let Some(id) = attribute::<i64>(&a, "id") else { anyhow::bail!("element without id at {}", reader.buffer_position()) };
+				current = Some(match kind {
+					b"node" => OsmObj::Node(Node {
+						id: NodeId(id),
+						tags: Tags::new(),
+						decimicro_lat: attribute(&a, "lat").map(decimicro).unwrap_or_default(),
+						decimicro_lon: attribute(&a, "lon").map(decimicro).unwrap_or_default(),
+					}),
+					b"way" => OsmObj::Way(Way { id: WayId(id), tags: Tags::new(), nodes: vec![] }),
+					_ => OsmObj::Relation(Relation { id: RelationId(id), tags: Tags::new(), refs: vec![] }),
+				});
+				if empty && let (Some(action), Some(obj)) = (action, current.take()) {
+					changes.push(Change { action, obj });
+				}
+			},
+			b"tag" => {
+				let (Some(k), Some(v)) = (a.get(b"k".as_slice()), a.get(b"v".as_slice())) else { continue };
+				let tags = match current {
+					Some(OsmObj::Node(ref mut n)) => &mut n.tags,
+					Some(OsmObj::Way(ref mut w)) => &mut w.tags,
+					Some(OsmObj::Relation(ref mut r)) => &mut r.tags,
+					None => continue,
+				};
+				tags.insert(k.as_str().into(), v.as_str().into());
+			},
+			b"nd" => if let (Some(OsmObj::Way(way)), Some(id)) = (current.as_mut(), attribute(&a, "ref")) {
+				way.nodes.push(NodeId(id));
+			},
+			b"member" => {
+				let Some(OsmObj::Relation(ref mut relation)) = current else { continue };
+				let (Some(member_type), Some(id)) = (a.get(b"type".as_slice()), attribute::<i64>(&a, "ref")) else { continue };
+				let member = match member_type.as_str() {
+					"node" => OsmId::Node(NodeId(id)),
+					"way" => OsmId::Way(WayId(id)),
+					"relation" => OsmId::Relation(RelationId(id)),
+					_ => continue,
+				};
+				let role = a.get(b"role".as_slice()).map(|r| r.as_str()).unwrap_or_default().into();
+				relation.refs.push(Ref { member, role });
+			},
+			_ => {},
+		}
+	}
+	Ok(changes)
+}
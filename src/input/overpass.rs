@@ -0,0 +1,297 @@
+// https://wiki.openstreetmap.org/wiki/OSM_XML
+// https://dev.overpass-api.de/overpass-doc/en/targets/formats.html#extras
+
+use std::{
+	collections::{HashMap, HashSet, VecDeque},
+	io::BufRead,
+};
+
+use osmpbfreader::{Node, NodeId, OsmId, OsmObj, Ref, Relation, RelationId, Tags, Way, WayId};
+use quick_xml::events::{BytesStart, Event};
+
+/// Streams objects of OSM XML including Overpass `out geom` variant, where ways and relation members carry their coordinates instead of referring to nodes.
+/// Coordinates become untagged nodes passed before the element using them, member ways without a `<way>` element become ways of their own,
+/// their nodes get synthetic negative ids. Equal coordinates share one node, so ways touching each other can still be merged into rings by node ids
+pub struct OverpassReader<R: BufRead> {
+	reader: quick_xml::Reader<R>,
+	buf: Vec<u8>,
+	current: Current,
+	/// member way with inline geometry: id and nodes
+	member_way: Option<(WayId, Vec<NodeId>)>,
+	/// objects completed by the last event
+	ready: VecDeque<OsmObj>,
+	geometry: Geometry,
+	done: bool,
+}
+
+/// What `out geom` coordinates need to be turned into nodes once, stays empty for plain OSM XML
+#[derive(Default)]
+struct Geometry {
+	/// `(decimicro_lat, decimicro_lon)` to node with these coordinates
+	coord_ids: HashMap<(i32, i32), NodeId>,
+	/// passed on already
+	nodes: HashSet<NodeId>,
+	ways: HashSet<WayId>,
+	next_synthetic_id: i64,
+	/// `<osm generator="Overpass API ...">`, so `<node>` and `<way>` elements are remembered too and aren't repeated by coordinates of later members
+	overpass: bool,
+}
+
+enum Current {
+	None,
+	Node(Node),
+	Way(Way),
+	Relation(Relation),
+}
+
+pub(super) fn attributes(e: &BytesStart) -> anyhow::Result<HashMap<Vec<u8>, String>> {
+	e.attributes()
+		.map(|a| {
+			let a = a?;
+			Ok((a.key.as_ref().to_vec(), a.unescape_value()?.into_owned()))
+		})
+		.collect()
+}
+
+pub(super) fn attribute<T: std::str::FromStr>(attributes: &HashMap<Vec<u8>, String>, key: &str) -> Option<T> {
+	attributes.get(key.as_bytes())?.parse().ok()
+}
+
+pub(super) fn decimicro(degrees: f64) -> i32 {
+	(degrees * 1e7).round() as i32
+}
+
+impl<R: BufRead> OverpassReader<R> {
+	pub fn new(input: R) -> Self {
+		let mut reader = quick_xml::Reader::from_reader(input);
+		reader.config_mut().trim_text(true);
+		Self {
+			reader,
+			buf: Vec::new(),
+			current: Current::None,
+			member_way: None,
+			ready: VecDeque::new(),
+			geometry: Geometry { next_synthetic_id: -1, ..Default::default() },
+			done: false,
+		}
+	}
+
+	/// Handles one event, `false` at the end of the document
+	fn read_event(&mut self) -> anyhow::Result<bool> {
+		let mut buf = std::mem::take(&mut self.buf);
+		buf.clear();
+		let result = self.handle_event(&mut buf);
+		self.buf = buf;
+		result
+	}
+
+	fn handle_event(&mut self, buf: &mut Vec<u8>) -> anyhow::Result<bool> {
+		let event = self.reader.read_event_into(buf)
+			.map_err(|e| anyhow::anyhow!("bad osm xml at {}: {e}", self.reader.error_position()))?;
+		let (e, empty) = match event {
+			Event::Start(ref e) => (e, false),
+			Event::Empty(ref e) => (e, true),
+			Event::End(ref e) => {
+				match e.local_name().as_ref() {
+					b"node" | b"way" | b"relation" => self.close(),
+					b"member" => if let Some((id, nodes)) = self.member_way.take()
+						&& self.geometry.ways.insert(id)
+					{
+						self.ready.push_back(OsmObj::Way(Way { id, tags: Tags::new(), nodes }));
+					},
+					_ => {},
+				}
+				return Ok(true);
+			},
+			Event::Eof => return Ok(false),
+			_ => return Ok(true),
+		};
+		let a = attributes(e)?;
+
+		match e.local_name().as_ref() {
+			b"osm" => self.geometry.overpass = a.get(b"generator".as_slice()).is_some_and(|g| g.starts_with("Overpass API")),
+			b"node" => {
+				let (Some(id), Some(lat), Some(lon)) = (attribute(&a, "id"), attribute(&a, "lat"), attribute(&a, "lon")) else {
+					anyhow::bail!("node without id or coordinates at {}", self.reader.buffer_position());
+				};
+				let node = Node { id: NodeId(id), tags: Tags::new(), decimicro_lat: decimicro(lat), decimicro_lon: decimicro(lon) };
+				self.current = Current::Node(node);
+			},
+			b"way" => {
+				let Some(id) = attribute(&a, "id") else { anyhow::bail!("way without id at {}", self.reader.buffer_position()) };
+				self.current = Current::Way(Way { id: WayId(id), tags: Tags::new(), nodes: vec![] });
+			},
+			b"relation" => {
+				let Some(id) = attribute(&a, "id") else { anyhow::bail!("relation without id at {}", self.reader.buffer_position()) };
+				self.current = Current::Relation(Relation { id: RelationId(id), tags: Tags::new(), refs: vec![] });
+			},
+			b"tag" => {
+				let (Some(k), Some(v)) = (a.get(b"k".as_slice()), a.get(b"v".as_slice())) else { return Ok(true) };
+				let tags = match self.current {
+					Current::Node(ref mut n) => &mut n.tags,
+					Current::Way(ref mut w) => &mut w.tags,
+					Current::Relation(ref mut r) => &mut r.tags,
+					Current::None => return Ok(true),
+				};
+				tags.insert(k.as_str().into(), v.as_str().into());
+			},
+			b"nd" => {
+				let coords = attribute::<f64>(&a, "lat").zip(attribute::<f64>(&a, "lon")).map(|(lat, lon)| (decimicro(lat), decimicro(lon)));
+				let node_id = match (attribute(&a, "ref"), coords) {
+					(Some(id), coords) => {
+						let id = NodeId(id);
+						if let Some(coords) = coords {
+							self.add_node(id, coords);
+						}
+						id
+					},
+					(None, Some(coords)) => self.synthetic_node(coords),
+					// outside of the query bbox
+					(None, None) => return Ok(true),
+				};
+				if let Some((_, ref mut nodes)) = self.member_way {
+					nodes.push(node_id);
+				}
+				else if let Current::Way(ref mut way) = self.current {
+					way.nodes.push(node_id);
+				}
+			},
+			b"member" => {
+				let Current::Relation(ref mut relation) = self.current else { return Ok(true) };
+				let (Some(member_type), Some(id)) = (a.get(b"type".as_slice()), attribute::<i64>(&a, "ref")) else { return Ok(true) };
+				let role = a.get(b"role".as_slice()).map(|r| r.as_str()).unwrap_or_default().into();
+				let member = match member_type.as_str() {
+					"node" => OsmId::Node(NodeId(id)),
+					"way" => OsmId::Way(WayId(id)),
+					"relation" => OsmId::Relation(RelationId(id)),
+					_ => return Ok(true),
+				};
+				relation.refs.push(Ref { member, role });
+
+				match member {
+					OsmId::Node(node_id) => if let Some((lat, lon)) = attribute::<f64>(&a, "lat").zip(attribute::<f64>(&a, "lon")) {
+						self.add_node(node_id, (decimicro(lat), decimicro(lon)));
+					},
+					OsmId::Way(way_id) if !empty => self.member_way = Some((way_id, vec![])),
+					_ => {},
+				}
+			},
+			_ => {},
+		}
+
+		if empty && matches!(e.local_name().as_ref(), b"node" | b"way" | b"relation") {
+			self.close();
+		}
+		Ok(true)
+	}
+
+	/// Untagged node known only by coordinates, passed once
+	fn add_node(&mut self, id: NodeId, (lat, lon): (i32, i32)) {
+		self.geometry.coord_ids.entry((lat, lon)).or_insert(id);
+		if self.geometry.nodes.insert(id) {
+			self.ready.push_back(OsmObj::Node(Node { id, tags: Tags::new(), decimicro_lat: lat, decimicro_lon: lon }));
+		}
+	}
+
+	fn synthetic_node(&mut self, coords: (i32, i32)) -> NodeId {
+		if let Some(id) = self.geometry.coord_ids.get(&coords) {
+			return *id;
+		}
+		let id = NodeId(self.geometry.next_synthetic_id);
+		self.geometry.next_synthetic_id -= 1;
+		self.add_node(id, coords);
+		id
+	}
+
+	/// Passes the current element on. A `<node>` that came by coordinates already is passed again only with its tags
+	fn close(&mut self) {
+		let geometry = &mut self.geometry;
+		let obj = match std::mem::replace(&mut self.current, Current::None) {
+			Current::Node(node) => {
+				if geometry.overpass {
+					geometry.coord_ids.entry((node.decimicro_lat, node.decimicro_lon)).or_insert(node.id);
+				}
+				let seen = match geometry.overpass {
+					true => !geometry.nodes.insert(node.id),
+					false => geometry.nodes.contains(&node.id),
+				};
+				if seen && node.tags.is_empty() {
+					return;
+				}
+				OsmObj::Node(node)
+			},
+			Current::Way(way) => {
+				if geometry.overpass {
+					geometry.ways.insert(way.id);
+				}
+				OsmObj::Way(way)
+			},
+			Current::Relation(relation) => OsmObj::Relation(relation),
+			Current::None => return,
+		};
+		self.ready.push_back(obj);
+	}
+}
+
+impl<R: BufRead> Iterator for OverpassReader<R> {
+	type Item = anyhow::Result<OsmObj>;
+
+	fn next(&mut self) -> Option<Self::Item> {
+		loop {
+			if let Some(obj) = self.ready.pop_front() {
+				return Some(Ok(obj));
+			}
+			if self.done {
+				return None;
+			}
+			match self.read_event() {
+				Ok(true) => {},
+				Ok(false) => self.done = true,
+				Err(e) => {
+					self.done = true;
+					return Some(Err(e));
+				},
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn read(xml: &str) -> Vec<OsmObj> {
+		OverpassReader::new(xml.as_bytes()).collect::<anyhow::Result<_>>().unwrap()
+	}
+
+	#[test]
+	fn elements_come_one_at_a_time() {
+		let xml = r#"<osm version="0.6"><node id="1" lat="1" lon="2"><tag k="place" v="city"/></node><way id="2"><nd ref="1"/>"#;
+		let mut reader = OverpassReader::new(xml.as_bytes());
+		let node = reader.next().unwrap().unwrap();
+		assert_eq!(node.node().map(|n| (n.id.0, n.decimicro_lat, n.decimicro_lon)), Some((1, 10_000_000, 20_000_000)));
+		assert!(node.tags().contains("place", "city"));
+		// the document breaks off after the first element
+		assert!(reader.next().is_none_or(|next| next.is_err()));
+	}
+
+	#[test]
+	fn out_geom_coordinates_become_nodes_once() {
+		let xml = r#"<osm version="0.6" generator="Overpass API 0.7.62">
+			<node id="100" lat="0" lon="0"><tag k="place" v="city"/></node>
+			<relation id="1">
+				<member type="way" ref="10" role="outer"><nd lat="0" lon="0"/><nd lat="0" lon="1"/><nd lat="1" lon="1"/></member>
+				<member type="way" ref="11" role="outer"><nd lat="1" lon="1"/><nd lat="1" lon="0"/><nd lat="0" lon="0"/></member>
+				<member type="node" ref="100" role="label" lat="0" lon="0"/>
+				<tag k="type" v="boundary"/>
+			</relation>
+		</osm>"#;
+		let elements = read(xml);
+		let nodes = elements.iter().filter_map(|obj| obj.node()).map(|n| (n.id.0, n.tags.len())).collect::<Vec<_>>();
+		// the city is a corner of both ways and isn't repeated untagged by the label member
+		assert_eq!(nodes, vec![(100, 1), (-1, 0), (-2, 0), (-3, 0)]);
+		let ways = elements.iter().filter_map(|obj| obj.way()).map(|w| (w.id.0, w.nodes.iter().map(|n| n.0).collect::<Vec<_>>())).collect::<Vec<_>>();
+		assert_eq!(ways, vec![(10, vec![100, -1, -2]), (11, vec![-2, -3, 100])]);
+		assert_eq!(elements.last().and_then(|obj| obj.relation()).map(|r| r.refs.len()), Some(3));
+	}
+}
@@ -0,0 +1,189 @@
+// https://wiki.openstreetmap.org/wiki/PBF_Format
+// https://docs.osmcode.org/osmium/latest/osmium-add-locations-to-ways.html
+
+use std::{
+	collections::HashMap,
+	fs::File,
+	io::{BufWriter, Write},
+	path::Path,
+};
+
+use flate2::{write::ZlibEncoder, Compression};
+use osmpbfreader::{
+	fileformat::{Blob, BlobHeader},
+	osmformat::{self, DenseNodes, HeaderBlock, PrimitiveBlock, PrimitiveGroup, Relation_MemberType, StringTable},
+	Node, OsmId, Relation, Tags, Way,
+};
+use protobuf::{Message, RepeatedField};
+
+/// Elements per block, as osmium writes
+const BLOCK_SIZE: usize = 8000;
+/// Fields of `Way` holding node coordinates, not in osmpbfreader's `osmformat.proto`
+const WAY_LAT: u32 = 9;
+const WAY_LON: u32 = 10;
+
+/// Way with decimicro `(lat, lon)` of its nodes
+pub type LocatedWay = (Way, Vec<(i32, i32)>);
+
+/// String table of one block, index 0 is the empty string by the format
+struct Strings {
+	table: Vec<Vec<u8>>,
+	index: HashMap<String, u32>,
+}
+
+impl Strings {
+	fn new() -> Self {
+		Self { table: vec![vec![]], index: HashMap::new() }
+	}
+
+	fn id(&mut self, s: &str) -> u32 {
+		if let Some(id) = self.index.get(s) {
+			return *id;
+		}
+		let id = self.table.len() as u32;
+		self.table.push(s.as_bytes().to_vec());
+		self.index.insert(s.to_string(), id);
+		id
+	}
+
+	fn tags(&mut self, tags: &Tags) -> (Vec<u32>, Vec<u32>) {
+		tags.iter().map(|(k, v)| (self.id(k), self.id(v))).unzip()
+	}
+}
+
+fn zigzag(value: i64, out: &mut Vec<u8>) {
+	let mut value = ((value << 1) ^ (value >> 63)) as u64;
+	while value >= 0x80 {
+		out.push(value as u8 | 0x80);
+		value >>= 7;
+	}
+	out.push(value as u8);
+}
+
+fn deltas(values: impl Iterator<Item = i64>) -> Vec<i64> {
+	let mut last = 0;
+	values.map(|value| { let delta = value - last; last = value; delta }).collect()
+}
+
+fn write_blob(out: &mut impl Write, blob_type: &str, data: Vec<u8>) -> anyhow::Result<()> {
+	let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+	encoder.write_all(&data)?;
+	let mut blob = Blob::new();
+	blob.set_raw_size(data.len() as i32);
+	blob.set_zlib_data(encoder.finish()?);
+	let blob = blob.write_to_bytes()?;
+
+	let mut header = BlobHeader::new();
+	header.set_field_type(blob_type.to_string());
+	header.set_datasize(blob.len() as i32);
+	let header = header.write_to_bytes()?;
+
+	out.write_all(&(header.len() as u32).to_be_bytes())?;
+	out.write_all(&header)?;
+	out.write_all(&blob)?;
+	Ok(())
+}
+
+fn nodes_group(strings: &mut Strings, nodes: &[Node]) -> PrimitiveGroup {
+	let mut dense = DenseNodes::new();
+	dense.id = deltas(nodes.iter().map(|n| n.id.0));
+	dense.lat = deltas(nodes.iter().map(|n| n.decimicro_lat as i64));
+	dense.lon = deltas(nodes.iter().map(|n| n.decimicro_lon as i64));
+	for node in nodes {
+		for (k, v) in node.tags.iter() {
+			dense.keys_vals.extend([strings.id(k) as i32, strings.id(v) as i32]);
+		}
+		dense.keys_vals.push(0);
+	}
+	let mut group = PrimitiveGroup::new();
+	group.set_dense(dense);
+	group
+}
+
+/// Ways without locations are written as usual
+fn ways_group(strings: &mut Strings, ways: &[LocatedWay]) -> PrimitiveGroup {
+	let mut group = PrimitiveGroup::new();
+	for (way, locations) in ways {
+		let mut raw = osmformat::Way::new();
+		raw.set_id(way.id.0);
+		(raw.keys, raw.vals) = strings.tags(&way.tags);
+		raw.refs = deltas(way.nodes.iter().map(|n| n.0));
+		if locations.is_empty() {
+			group.ways.push(raw);
+			continue;
+		}
+		for (field, coordinates) in [(WAY_LAT, deltas(locations.iter().map(|l| l.0 as i64))), (WAY_LON, deltas(locations.iter().map(|l| l.1 as i64)))] {
+			let mut packed = Vec::with_capacity(coordinates.len() * 3);
+			coordinates.into_iter().for_each(|c| zigzag(c, &mut packed));
+			raw.unknown_fields.add_length_delimited(field, packed);
+		}
+		group.ways.push(raw);
+	}
+	group
+}
+
+fn relations_group(strings: &mut Strings, relations: &[Relation]) -> PrimitiveGroup {
+	let mut group = PrimitiveGroup::new();
+	for relation in relations {
+		let mut raw = osmformat::Relation::new();
+		raw.set_id(relation.id.0);
+		(raw.keys, raw.vals) = strings.tags(&relation.tags);
+		raw.roles_sid = relation.refs.iter().map(|rf| strings.id(&rf.role) as i32).collect();
+		raw.memids = deltas(relation.refs.iter().map(|rf| rf.member.inner_id()));
+		raw.types = relation.refs.iter().map(|rf| match rf.member {
+			OsmId::Node(_) => Relation_MemberType::NODE,
+			OsmId::Way(_) => Relation_MemberType::WAY,
+			OsmId::Relation(_) => Relation_MemberType::RELATION,
+		}).collect();
+		group.relations.push(raw);
+	}
+	group
+}
+
+/// PBF written block by block, granularity stays at the default 100 nanodegrees with zero offsets, so coordinates are written as decimicro
+pub struct PbfWriter {
+	out: BufWriter<File>,
+}
+
+impl PbfWriter {
+	pub fn create(path: &Path, optional_features: &[&str]) -> anyhow::Result<Self> {
+		let mut out = BufWriter::new(File::create(path)?);
+		let mut header = HeaderBlock::new();
+		header.required_features = RepeatedField::from_vec(vec!["OsmSchema-V0.6".to_string(), "DenseNodes".to_string()]);
+		header.optional_features = RepeatedField::from_vec(optional_features.iter().map(|f| f.to_string()).collect());
+		header.set_writingprogram("planet-filtered".to_string());
+		write_blob(&mut out, "OSMHeader", header.write_to_bytes()?)?;
+		Ok(Self { out })
+	}
+
+	/// One block with a group per kind present
+	pub fn block(&mut self, nodes: &[Node], ways: &[LocatedWay], relations: &[Relation]) -> anyhow::Result<()> {
+		let mut strings = Strings::new();
+		let mut groups = Vec::new();
+		if !nodes.is_empty() { groups.push(nodes_group(&mut strings, nodes)); }
+		if !ways.is_empty() { groups.push(ways_group(&mut strings, ways)); }
+		if !relations.is_empty() { groups.push(relations_group(&mut strings, relations)); }
+
+		let mut table = StringTable::new();
+		table.s = RepeatedField::from_vec(strings.table);
+		let mut block = PrimitiveBlock::new();
+		block.set_stringtable(table);
+		block.primitivegroup = RepeatedField::from_vec(groups);
+		write_blob(&mut self.out, "OSMData", block.write_to_bytes()?)
+	}
+
+	pub fn finish(mut self) -> anyhow::Result<()> {
+		self.out.flush()?;
+		Ok(())
+	}
+}
+
+/// PBF with `LocationsOnWays`: ways carry decimicro `(lat, lon)` of their nodes, so untagged way nodes can be left out.
+/// Elements have to be sorted by id, the file is marked `Sort.Type_then_ID`
+pub fn write(path: &Path, nodes: &[Node], ways: &[LocatedWay], relations: &[Relation]) -> anyhow::Result<()> {
+	let mut writer = PbfWriter::create(path, &["LocationsOnWays", "Sort.Type_then_ID"])?;
+	for chunk in nodes.chunks(BLOCK_SIZE) { writer.block(chunk, &[], &[])?; }
+	for chunk in ways.chunks(BLOCK_SIZE) { writer.block(&[], chunk, &[])?; }
+	for chunk in relations.chunks(BLOCK_SIZE) { writer.block(&[], &[], chunk)?; }
+	writer.finish()
+}
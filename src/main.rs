@@ -3,21 +3,28 @@
 
 mod utils;
 mod converter;
+mod eject_via_planet;
+mod export;
+mod extract;
+mod input;
+mod locations_on_ways;
+mod manifest;
+mod snapshot;
 
 use std::{
 	str::FromStr,
 	time::Duration,
 	path::{Path, PathBuf},
 	fs::File,
-	collections::{HashMap, HashSet}, 
+	collections::{BTreeMap, HashMap, HashSet}, 
 	sync::{atomic::{AtomicU64, Ordering}, Arc}, 
 };
 
 use chrono::Local;
-use converter::merge_nodes;
-use geo::{Centroid, Contains, Geometry, HasDimensions};
+use converter::build_multipolygon;
+use geo::{Centroid, Contains, Geometry};
 use indicatif::ProgressBar;
-use osmpbfreader::{Node, OsmObj, OsmPbfReader, Relation, Tags, Way};
+use osmpbfreader::{Node, NodeId, OsmObj, OsmPbfReader, Relation, Tags, Way};
 use parking_lot::{RwLock, Mutex};
 use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, IntoParallelRefMutIterator, ParallelIterator};
 // use serde::Serialize;
@@ -25,6 +32,7 @@ use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, IntoParallelRef
 trait Url {
 	fn url(&self) -> String;
 	fn str_id(&self) -> String;
+	fn osm_id(&self) -> String;
 }
 
 impl<T: Into<OsmObj> + Clone> Url for T where OsmObj: From<T> {
@@ -49,6 +57,16 @@ impl<T: Into<OsmObj> + Clone> Url for T where OsmObj: From<T> {
 		};
 		format!("{}/{}/{}", s, obj.id().inner_id(), obj.tags().get("name").map(|x| x.as_str()).unwrap_or("<NO NAME>"))
 	}
+
+	fn osm_id(&self) -> String {
+		let obj = OsmObj::from(self.clone());
+		let s = match obj {
+			OsmObj::Way(_) => "way",
+			OsmObj::Relation(_) => "relation",
+			OsmObj::Node(_) => "node",
+		};
+		format!("{}/{}", s, obj.id().inner_id())
+	}
 }
 
 fn main() -> anyhow::Result<()> {
@@ -66,11 +84,31 @@ fn main() -> anyhow::Result<()> {
 		return Ok(());
 	}
 
+	if last == "--update" {
+		update_cache()?;
+		return Ok(());
+	}
+
 	if last == "--pipeline1" {
 		pipeline1()?;
 		return Ok(());
 	}
 
+	if last == "--lookup" {
+		lookup()?;
+		return Ok(());
+	}
+
+	if last == "--reextract" {
+		extract::reextract_changed(&std::env::var("PREVIOUS_SRC")?, &std::env::var("OSM_SRC")?, Path::new(&std::env::var("EXTRACT_DIR")?))?;
+		return Ok(());
+	}
+
+	if last == "--extract" {
+		extract::extract_polygons(&std::env::var("OSM_SRC")?)?;
+		return Ok(());
+	}
+
 	eprintln!("wrong arg");
 
 	Ok(())
@@ -81,7 +119,7 @@ structstruck::strike! {
 	#[strikethrough[derive(Debug, Clone)]]
 	// #[strikethrough[serde(rename_all = "snake_case")]]
 	struct Place {
-		mapped_type: #[derive(PartialEq, PartialOrd, Eq, Ord)] enum {
+		mapped_type: #[derive(PartialEq, PartialOrd, Eq, Ord, serde::Serialize, serde::Deserialize)] enum {
 			City,
 			Region, 
 			Country
@@ -90,6 +128,8 @@ structstruck::strike! {
 		geometry: Geometry,
 		tags: HashMap<String, String>,
 		center: geo::Coord,
+		/// how `center` was found, like `role_label` or `centroid`
+		center_role: String,
 		source: OsmObj
 	}
 }
@@ -107,115 +147,244 @@ structstruck::strike! {
 // unsafe impl Send for PlacePtr {}
 // unsafe impl Sync for PlacePtr {}
 
+impl MappedType {
+	fn name(&self) -> &'static str {
+		match self {
+			MappedType::Country => "country",
+			MappedType::Region => "region",
+			MappedType::City => "city",
+		}
+	}
+}
+
 impl Place {
 	fn name(&self) -> &String {
 		&self.tags["name"]
 	}
-}
 
-fn ways_to_way_nodes<F: Fn(&Way) -> bool + Sync>(elements: &Vec<OsmObj>, way_filter: F) -> HashMap<Way, Vec<Node>> {
-	let ways_to_node_ids = elements.par_iter().filter_map(|el| {
-		let way = el.way()?;
-		way_filter(&way).then_some((way, way.nodes.clone()))
-	}).collect::<HashMap<_, _>>();
+	/// ISO3166-1 code for countries, ISO3166-2 for subdivisions
+	fn iso_code(&self) -> Option<&String> {
+		ISO3166_1_KEYS.iter().find_map(|key| self.tags.get(*key)).or_else(|| self.tags.get("ISO3166-2"))
+	}
 
-	let node_ids_to_nodes = {
-		let needed_nodes_ids = ways_to_node_ids.values().flatten().collect::<HashSet<_>>();
+	fn wikidata(&self) -> Option<&String> {
+		self.tags.get("wikidata")
+	}
 
-		elements.par_iter().filter_map(|el| {
-			let node = el.node().filter(|node| needed_nodes_ids.contains(&node.id)).cloned()?;
-			(node.id, node).into()
-		}).collect::<HashMap<_, _>>()
-	};
+	fn osm_ref(&self) -> Option<&String> {
+		self.tags.get("ref")
+	}
 
-	let ways_to_nodes = ways_to_node_ids.into_par_iter().map(|(way, node_ids)| {
-		let nodes = node_ids.into_par_iter().filter_map(|n| node_ids_to_nodes.get(&n).cloned()).collect::<Vec<_>>();
-		(way.clone(), nodes)
-	}).collect::<HashMap<_, _>>();
+	/// Same shape `extract_polygons` writes: tags with `osm_url`, `center` and `center_role` as properties.
+	/// Additionally carries `mapped_type` and `parents` (osm ids like `relation/123`) from the hierarchy
+	fn to_feature(&self) -> geojson::Feature {
+		let mut properties = geojson::JsonObject::from_iter(self.tags.iter().map(|(k, v)| (k.clone(), v.clone().into())));
+		properties.insert("osm_url".into(), self.source.url().into());
+		properties.insert("center".into(), format!("{},{}", self.center.x, self.center.y).into());
+		properties.insert("center_role".into(), self.center_role.clone().into());
+		properties.insert("mapped_type".into(), self.mapped_type.name().into());
+		properties.insert("parents".into(), self.parents.iter().map(|p| p.read().source.osm_id()).collect::<Vec<_>>().into());
+
+		geojson::Feature {
+			id: geojson::feature::Id::String(self.source.str_id()).into(),
+			properties: properties.into(),
+			geometry: Some(geojson::Geometry::from(&self.geometry)),
+			..Default::default()
+		}
+	}
+}
 
-	ways_to_nodes
+/// Ways passing `way_filter` with their nodes located in `locations`, untagged
+fn ways_to_way_nodes<F: Fn(&Way) -> bool + Sync>(elements: &Vec<OsmObj>, locations: &input::Locations, way_filter: F) -> HashMap<Way, Vec<Node>> {
+	elements.par_iter().filter_map(|el| {
+		let way = el.way().filter(|way| way_filter(way))?;
+		let nodes = way.nodes.iter().filter_map(|node_id| locations.node(*node_id)).collect::<Vec<_>>();
+		Some((way.clone(), nodes))
+	}).collect::<HashMap<_, _>>()
 }
 
-fn relations_to_relation_nodes<F: Fn(&Relation) -> bool + Sync>(elements: &Vec<OsmObj>, rel_filter: F) -> HashMap<Relation, Vec<Vec<Node>>> {
+type RelationsNodes = HashMap<Relation, Vec<(String, Vec<Node>)>>;
+
+/// Relations passing `rel_filter` with `(role, nodes)` of their member ways.
+/// Relations with member ways or their nodes missing, like ones cut at extract edges, are returned apart instead of assembled from a part
+fn relations_to_relation_nodes<F: Fn(&Relation) -> bool + Sync>(elements: &Vec<OsmObj>, locations: &input::Locations, rel_filter: F) -> (RelationsNodes, Vec<Relation>) {
 	let way_ids = Mutex::new(HashSet::with_capacity(10_000));
 
 	let relations_to_way_ids = elements.par_iter().filter_map(|el| {
 		let relation = el.relation().cloned().filter(|rel| rel_filter(rel))?;
-		let relation_way_ids = relation.refs.iter().filter_map(|rref| rref.member.way().inspect(|way_id| { way_ids.lock().insert(way_id.clone()); })).collect::<Vec<_>>();
+		let relation_way_ids = relation.refs.iter()
+			.filter_map(|rref| rref.member.way().inspect(|way_id| { way_ids.lock().insert(way_id.clone()); }).map(|way_id| (rref.role.to_string(), way_id)))
+			.collect::<Vec<_>>();
 		return Some((relation, relation_way_ids));
 	}).collect::<HashMap<_, _>>();
 
-	let ways_to_nodes = ways_to_way_nodes(&elements, |way| way_ids.lock().contains(&way.id))
+	let ways_to_nodes = ways_to_way_nodes(&elements, locations, |way| way_ids.lock().contains(&way.id))
 		.into_par_iter()
-		.map(|(way, nodes)| (way.id, nodes))
+		.map(|(way, nodes)| (way.id, (way.nodes.len() == nodes.len(), nodes)))
 		.collect::<HashMap<_, _>>();
 
-	let result = relations_to_way_ids.into_par_iter().map(|(relation, way_ids)| {
-		(relation, way_ids.iter().filter_map(|wid| ways_to_nodes.get(wid).cloned()).collect())
+	let (complete, incomplete): (Vec<_>, Vec<_>) = relations_to_way_ids.into_par_iter()
+		.partition(|(_, way_ids)| way_ids.iter().all(|(_, wid)| ways_to_nodes.get(wid).is_some_and(|(complete, _)| *complete)));
+
+	let result = complete.into_par_iter().map(|(relation, way_ids)| {
+		(relation, way_ids.into_iter().filter_map(|(role, wid)| Some((role, ways_to_nodes.get(&wid)?.1.clone()))).collect())
 	}).collect::<HashMap<_, _>>();
 
-	result
+	(result, incomplete.into_iter().map(|(relation, _)| relation).collect())
 } 
 
-fn build_polygon(r: Vec<Vec<Node>>) -> Option<geo::Geometry> {
-	let coords = merge_nodes(r.to_vec())
-		.into_iter()	
-		.map(|wn| wn.iter().map(|node| geo::Coord { x: node.lon(), y: node.lat() }).collect::<Vec<_>>())
-		.collect::<Vec<_>>();
+/// Picks label node of relation among `candidates`: `label` role members first, then other members.
+/// Among members of the same rank one sharing `wikidata` with the relation wins, then the first one
+fn find_label_node(rel: &Relation, candidates: &HashMap<NodeId, &Node>) -> Option<NodeId> {
+	let wikidata = rel.tags.get("wikidata");
+	rel.refs.iter()
+		.filter_map(|rf| rf.member.node().filter(|nid| candidates.contains_key(nid)).map(|nid| (nid, rf.role.as_str())))
+		.enumerate()
+		.min_by_key(|(i, (nid, role))| (*role != "label", wikidata.is_none() || candidates[nid].tags.get("wikidata") != wikidata, *i))
+		.map(|(_, (nid, _))| nid)
+}
 
-	let polygons = coords.iter().filter_map(|points| {
-		let exterior = geo::LineString(points.to_vec());
-		if exterior.is_empty() { return None }
-		geo::Polygon::new(exterior, vec![]).into()
-	}).collect::<Vec<_>>();
+fn member_center_role(rel: &Relation, node_id: NodeId) -> String {
+	rel.refs.iter()
+		.find(|rf| rf.member.node() == Some(node_id) && !rf.role.is_empty())
+		.map(|rf| format!("role_{}", rf.role))
+		.unwrap_or_else(|| "member".into())
+}
 
-	if polygons.is_empty() { return None; }
+fn pipeline1() -> anyhow::Result<()> {
+	let table_specs = export::table_specs_from_env()?;
 
-	let geometry = if polygons.len() == 1 { 
-		Geometry::Polygon(polygons[0].clone()) 
-	}
-	else { 
-		Geometry::MultiPolygon(geo::MultiPolygon(polygons.clone()))
+	let artifacts_dir = PathBuf::from(format!("./output/pipeline1_{}", Local::now().format("%d_%m_%H_%M_%S")));
+	std::fs::create_dir_all(&artifacts_dir);
+
+	let formats = export::formats_from_env(&[]);
+	let mut manifest = manifest::Manifest::new(pipeline1_filters(), formats.clone(), table_specs.iter().map(|s| s.file.clone()).collect());
+
+	let places = match std::env::var("PLACES_SNAPSHOT") {
+		Ok(snapshot_path) => {
+			println!("loading places from snapshot {snapshot_path}");
+			let rebuild_hierarchy = std::env::var("REBUILD_HIERARCHY").is_ok_and(|v| v == "1");
+			manifest.set_source(manifest::Source::Snapshot { path: snapshot_path.clone().into(), rebuild_hierarchy });
+			let places = manifest.stage("load_snapshot", || snapshot::load(Path::new(&snapshot_path)))?;
+			manifest.record_assembled(&places);
+			match rebuild_hierarchy {
+				true => manifest.stage("hierarchy", || build_hierarchy(places)),
+				false => places,
+			}
+		},
+		Err(_) => {
+			let places = match std::env::var("EXTRACTED_SRC") {
+				Ok(extracted_src) => {
+					println!("loading places from extracted {extracted_src}");
+					manifest.set_source(manifest::Source::Extracted { path: extracted_src.clone().into() });
+					manifest.stage("load_extracted", || eject_via_planet::load(Path::new(&extracted_src)))?
+				},
+				Err(_) => {
+					let osm_src = std::env::var("OSM_SRC")?;
+					manifest.set_source(manifest::Source::files(&input::Sources::open(&osm_src)?)?);
+					manifest.stage("assemble", || assemble_places(&osm_src, &artifacts_dir))?
+				},
+			};
+			manifest.record_assembled(&places);
+			let places = manifest.stage("hierarchy", || build_hierarchy(places));
+			println!("writing places snapshot");
+			manifest.stage("snapshot", || snapshot::save(&artifacts_dir.join("places.snapshot"), &places))?;
+			places
+		},
 	};
+	manifest.record_written(&places);
+
+	let sinks = export::sinks_from_env(&artifacts_dir, &[])?;
+	if !sinks.is_empty() {
+		println!("writing features");
+		manifest.stage("features", || -> anyhow::Result<()> {
+			places.par_iter().try_for_each(|place| -> anyhow::Result<()> {
+				let feature = place.read().to_feature();
+				for sink in sinks.iter() { sink.write(&feature)?; }
+				Ok(())
+			})?;
+			for sink in sinks.iter() { sink.finish()?; }
+			Ok(())
+		})?;
+	}
 
-	Some(geometry)
-}
+	manifest.stage("tables", || -> anyhow::Result<()> {
+		let rows = places.iter().map(hierarchy_row).collect::<Vec<_>>();
+		for spec in table_specs.iter() {
+			println!("writing {}", spec.file);
+			export::write_table(&artifacts_dir, spec, &rows)?;
+		}
 
+		if formats.iter().any(|f| f == "geoparquet") {
+			println!("writing table.parquet");
+			export::write_hierarchy_parquet(&artifacts_dir.join("table.parquet"), &table_specs, &rows)?;
+		}
+		Ok(())
+	})?;
 
-fn pipeline1() -> anyhow::Result<()> {
-	let osm_src = std::env::var("OSM_SRC")?;
+	manifest.write(&artifacts_dir.join("manifest.json"))?;
+	Ok(())
+}
 
-	let mut pbf = OsmPbfReader::new(File::open(Path::new(&osm_src))?);
+/// `<lon> <lat> --lookup` prints places containing the point from `PLACES_SNAPSHOT`
+fn lookup() -> anyhow::Result<()> {
+	let args = std::env::args().collect::<Vec<_>>();
+	let [.., lon, lat, _] = args.as_slice() else { anyhow::bail!("usage: <lon> <lat> --lookup") };
+	let point = geo::Point::new(lon.parse()?, lat.parse()?);
 
-	let artifacts_dir = PathBuf::from(format!("./output/pipeline1_{}", Local::now().format("%d_%m_%H_%M_%S")));
-	std::fs::create_dir_all(&artifacts_dir);
+	let places = snapshot::load(Path::new(&std::env::var("PLACES_SNAPSHOT")?))?;
+	for place in snapshot::lookup(&places, point) {
+		let place = place.read();
+		println!("{}\t{}\t{}", place.mapped_type.name(), place.source.osm_id(), place.name());
+	}
+	Ok(())
+}
 
-	println!("loading elements in memory");
-	let elements = pbf.par_iter()
-		.filter_map(|el| el.ok())
-		.collect::<Vec<_>>();
+/// Reads OSM file and builds countries, regions and cities without hierarchy
+fn assemble_places(osm_src: &str, artifacts_dir: &Path) -> anyhow::Result<Vec<SyncPlace>> {
+	let sources = input::Sources::open(osm_src)?;
+	let is_named_place = |tags: &Tags, places: &[&str]| tags.contains_key("name") && tags.get("place").is_some_and(|place| places.contains(&place.as_str()));
+
+	// region relations are the ones with a region node member, so these nodes are needed before relations
+	println!("reading region nodes ids");
+	let region_node_ids = sources.filter_map(|obj| obj.node().filter(|n| is_named_place(&n.tags, REGION_PLACES)).map(|n| n.id))?
+		.into_iter()
+		.collect::<HashSet<_>>();
+
+	let elements = sources.referenced(|obj| match obj {
+		OsmObj::Node(n) => n.tags.contains("place", "country") || is_named_place(&n.tags, REGION_PLACES) || is_named_place(&n.tags, CITY_PLACES),
+		OsmObj::Relation(rel) => rel.tags.contains_key("name")
+			&& (is_country(&rel.tags) || rel.refs.iter().any(|rf| rf.member.node().is_some_and(|nid| region_node_ids.contains(&nid)))),
+		OsmObj::Way(_) => false,
+	})?;
+	drop(region_node_ids);
+	println!("loaded {} OSM elements in memory", elements.len());
+	let locations = sources.locations(&elements)?;
 
 	// https://wiki.openstreetmap.org/wiki/Key:place
 	// https://wiki.openstreetmap.org/wiki/Places
 	// https://wiki.openstreetmap.org/wiki/Key:admin_level
 	
-	println!("finding country nodes");
+	println!("finding country label nodes");
 	let countries_nodes = elements.par_iter()
 		.filter_map(|el| el.node().filter(|n| n.tags.contains("place", "country")).cloned())
 		.collect::<Vec<_>>();
 
 	let relations_source_nodes = Mutex::new(HashMap::with_capacity(10_000));
 
-	println!("mapping country_nodes to relation/nodes");
-	let countries_relations = {
-		let nodes_hs = countries_nodes.par_iter().map(|rn| rn.id).collect::<HashSet<_>>();
-		let result = relations_to_relation_nodes(&elements, |rel|  {
-			let Some(name) = rel.tags.get("name") else { return false };
-			// if name.contains("land mass") { return false; }
-
-			return rel.refs.iter().find_map(|rf| rf.member.node().filter(|nid| nodes_hs.contains(&nid))).inspect(|nid| {
-				relations_source_nodes.lock().insert(rel.clone(), nid.clone());
-			}).is_some();
+	println!("finding country relations");
+	let (countries_relations, countries_incomplete) = {
+		let nodes_hm = countries_nodes.par_iter().map(|rn| (rn.id, rn)).collect::<HashMap<_, _>>();
+		let result = relations_to_relation_nodes(&elements, &locations, |rel|  {
+			if !rel.tags.contains_key("name") || !is_country(&rel.tags) { return false };
+
+			// place=country node is only used as a label, country itself is defined by relation tags
+			let label = find_label_node(rel, &nodes_hm)
+				.or_else(|| rel.refs.iter().find_map(|rf| rf.member.node().filter(|_| rf.role == "label")));
+			if let Some(nid) = label {
+				relations_source_nodes.lock().insert(rel.clone(), nid);
+			}
+			return true;
 		});
 		result
 	};
@@ -225,7 +394,7 @@ fn pipeline1() -> anyhow::Result<()> {
 			.filter_map(|x| x.url().into())
 			.collect::<Vec<_>>();
 		let mut cr = countries_relations.iter()
-			.filter_map(|x| format!("{} / {}", x.0.tags.get("name:en")?.to_string(), x.0.url()).into())
+			.filter_map(|x| format!("{} / {} / {}", iso3166_1(&x.0.tags)?, x.0.tags.get("name:en").or_else(|| x.0.tags.get("name"))?, x.0.url()).into())
 			.collect::<Vec<_>>();
 		cn.sort_unstable();
 		cr.sort_unstable();
@@ -236,22 +405,31 @@ fn pipeline1() -> anyhow::Result<()> {
 
 	println!("finding regions nodes");
 	let regions_nodes = elements.par_iter()
-		.filter_map(|el| el.node().filter(|n| n.tags.get("name").is_some() && n.tags.get("place").is_some_and(|place| ["region", "state", "province"].contains(&place.as_str()))).cloned())
+		.filter_map(|el| el.node().filter(|n| n.tags.get("name").is_some() && n.tags.get("place").is_some_and(|place| REGION_PLACES.contains(&place.as_str()))).cloned())
 		.collect::<Vec<_>>();
 
 	println!("mapping regions to relation/nodes");
-	let regions_relations = {
-		let nodes_hs = regions_nodes.par_iter().map(|rn| rn.id).collect::<HashSet<_>>();
-		relations_to_relation_nodes(&elements, |rel| {
+	let (regions_relations, regions_incomplete) = {
+		let nodes_hm = regions_nodes.par_iter().map(|rn| (rn.id, rn)).collect::<HashMap<_, _>>();
+		relations_to_relation_nodes(&elements, &locations, |rel| {
 			let Some(name) = rel.tags.get("name") else { return false };
 
-			return rel.refs.iter().find_map(|rf| rf.member.node().filter(|nid| nodes_hs.contains(&nid))).inspect(|nid| {
+			return find_label_node(rel, &nodes_hm).inspect(|nid| {
 				relations_source_nodes.lock().insert(rel.clone(), nid.clone());
 			}).is_some();
 		})
 	};
 
 	println!("regions_nodes: {}, regions_relations: {}", regions_nodes.len(), regions_relations.len());
+	{
+		let mut ir = countries_incomplete.iter().chain(regions_incomplete.iter())
+			.map(|x| format!("{} / {}", x.tags.get("name:en").or_else(|| x.tags.get("name")).map(|n| n.as_str()).unwrap_or_default(), x.url()))
+			.collect::<Vec<_>>();
+		ir.sort_unstable();
+
+		println!("{} relations are incomplete (cut at extract edges?) and skipped", ir.len());
+		std::fs::write(artifacts_dir.join("incomplete_relations.txt"), ir.join("\n"));
+	}
 	{
 		let mut rn = regions_nodes.iter()
 			.map(|x| x.tags.get("name:en").or_else(|| x.tags.get("name")).unwrap().to_string())
@@ -272,7 +450,7 @@ fn pipeline1() -> anyhow::Result<()> {
 		.filter_map(|el| 
 			el.node()
 				.filter(|n| n.tags.contains_key("name"))
-				.filter(|n| n.tags.get("place").is_some_and(|place| ["city", "town"].contains(&place.as_str())))
+				.filter(|n| n.tags.get("place").is_some_and(|place| CITY_PLACES.contains(&place.as_str())))
 				.cloned()
 		)
 		.collect::<Vec<_>>();
@@ -290,7 +468,7 @@ fn pipeline1() -> anyhow::Result<()> {
 			.filter_map(|el| el.node().filter(|n| node_ids.contains(&&n.id)).map(|n| (n.id, n)))
 			.collect::<HashMap<_, _>>();
 
-		f.into_iter().map(|(rel, nid)| (rel, node_ids_to_nodes[&nid])).collect::<HashMap<_, _>>()
+		f.into_iter().filter_map(|(rel, nid)| (rel, *node_ids_to_nodes.get(&nid)?).into()).collect::<HashMap<_, _>>()
 	};
 
 
@@ -301,7 +479,7 @@ fn pipeline1() -> anyhow::Result<()> {
 			return None;
 		}
 		if rel.tags.get("name").unwrap() == "United States" {
-			let geometry = build_polygon(nnodes.clone())?;
+			let geometry = build_multipolygon(nnodes.clone())?;
 			
 			std::fs::write(artifacts_dir.join("us.json"), geojson::GeoJson::Feature(geojson::Feature { 
 				geometry: geojson::Geometry::from(match geometry { 
@@ -313,13 +491,19 @@ fn pipeline1() -> anyhow::Result<()> {
 			println!("written debug us");
 		}
 
-		let source_node = relations_source_nodes[&rel];
+		let geometry = build_multipolygon(nnodes)?;
+		let (center, center_role) = match relations_source_nodes.get(&rel) {
+			Some(label_node) => (geo::Coord { x: label_node.lon(), y: label_node.lat() }, member_center_role(&rel, label_node.id)),
+			None => (geometry.centroid()?.into(), "centroid".to_string()),
+		};
+		let tags = rel.tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<_, _>>();
 		Place {
 			mapped_type: MappedType::Country,
 			parents: vec![],
-			geometry: build_polygon(nnodes)?,
-			center:  geo::Coord { x: source_node.lon(), y: source_node.lat() },
-			tags: rel.tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+			geometry,
+			center,
+			center_role,
+			tags,
 			source: rel.into(),
 		}.into()
 	}).collect::<Vec<_>>();
@@ -333,12 +517,14 @@ fn pipeline1() -> anyhow::Result<()> {
 		}
 
 		let source_node =  relations_source_nodes[&rel];
+		let tags = rel.tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<_, _>>();
 		Place {
 			mapped_type: MappedType::Region,
 			parents: vec![],
-			geometry: build_polygon(nnodes)?,
+			geometry: build_multipolygon(nnodes)?,
 			center:  geo::Coord { x: source_node.lon(), y: source_node.lat() },
-			tags: rel.tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+			center_role: member_center_role(&rel, source_node.id),
+			tags,
 			source: rel.into()
 		}.into()
 	}).collect::<Vec<_>>();
@@ -349,19 +535,31 @@ fn pipeline1() -> anyhow::Result<()> {
 			println!("city wo name: {}", node.id.0);
 			return None;
 		}
+		let tags = node.tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<_, _>>();
 		Place {
 			mapped_type: MappedType::City,
 			parents: vec![],
 			geometry: geo::Geometry::Point(geo::Point(geo::Coord { x: node.lon(), y: node.lat() })),
 			center:  geo::Coord { x: node.lon(), y: node.lat() },
-			tags: node.tags.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+			center_role: "node".into(),
+			tags,
 			source: node.into(),
 		}.into()
 	}).collect::<Vec<_>>();
 
-	let places_collections = [ places_countries, places_regions, places_cities, ].into_iter()
-		.map(|c| c.into_iter().map(|p| Arc::new(RwLock::new(p))).collect::<Vec<_>>() )
-		.collect::<Vec<_>>();
+	Ok([places_countries, places_regions, places_cities].into_iter()
+		.flatten()
+		.map(|p| Arc::new(RwLock::new(p)))
+		.collect())
+}
+
+/// Links every region to countries and every city to regions (or countries) containing its center.
+/// Places without parents are dropped
+fn build_hierarchy(places: Vec<SyncPlace>) -> Vec<SyncPlace> {
+	let places_collections = [MappedType::Country, MappedType::Region, MappedType::City]
+		.map(|mapped_type| places.iter().filter(|p| p.read().mapped_type == mapped_type).cloned().collect::<Vec<_>>());
+	drop(places);
+	places_collections.iter().flatten().for_each(|p| p.write().parents.clear());
 
 	println!("building graph");
 
@@ -409,68 +607,43 @@ fn pipeline1() -> anyhow::Result<()> {
 	places.retain(|p| !places_to_delete.contains(&p.read().source.str_id()));
 	drop(places_to_delete);
 
+	places
+}
 
-	use std::{fs::OpenOptions, io::{BufWriter, Write}, path::PathBuf};
-	let mut file = BufWriter::new(OpenOptions::new().create(true).append(true).open(&artifacts_dir.join("table.csv"))?);
-	file.write("country_en,country_ru,region_en,region_ru,city_en,city_ru,lon,lat,population\n".as_bytes())?;
-	println!("writing table");
-
-	for place in places.into_iter() {
-		#[derive(Default)]
-		struct Row {
-			country_en : Option<String>,
-			country_ru : Option<String>,
-			region_en  : Option<String>,
-			region_ru  : Option<String>,
-			city_en    : Option<String>,
-			city_ru    : Option<String>,
+/// Flattens place and its parents into a table row
+fn hierarchy_row(place: &SyncPlace) -> export::HierarchyRow {
+	fn traverse(row: &mut export::HierarchyRow, item: SyncPlace) {
+		let place = item.read();
+		let hierarchy_item = export::HierarchyItem {
+			osm_id: place.source.osm_id(),
+			iso_code: place.iso_code().cloned(),
+			osm_ref: place.osm_ref().cloned(),
+			wikidata: place.wikidata().cloned(),
+			tags: place.tags.clone(),
+		};
+		match place.mapped_type {
+			MappedType::Country => row.country = Some(hierarchy_item),
+			MappedType::Region => row.region = Some(hierarchy_item),
+			MappedType::City => row.city = Some(hierarchy_item),
 		}
-		impl Row {
-			fn get_names(item: &SyncPlace) -> (Option<String>, Option<String>) {
-				let tags = &item.read().tags;
-				(tags.get("name:en").or(tags.get("name")).map(|x| x.to_string()), tags.get("name:ru").map(|x| x.to_string()))
-			}
-			fn traverse(&mut self, item: SyncPlace) {
-				match item.read().mapped_type {
-					MappedType::Country => {
-						(self.country_en, self.country_ru) = Row::get_names(&item)
-					},
-					MappedType::Region => {
-						(self.region_en, self.region_ru) = Row::get_names(&item)
-					}
-					MappedType::City => {
-						(self.city_en, self.city_ru) = Row::get_names(&item)
-					},
-				}
-				for parent in item.read().parents.iter() {
-					self.traverse(parent.clone())
-				}
-			}
+		for parent in place.parents.iter() {
+			traverse(row, parent.clone())
 		}
-		let mut row = Row::default();
-		row.traverse(place.clone());
-
-		const STRING_EMPTY: String = String::new();
-		let center = place.read().geometry.centroid().unwrap();
-
-		let s = format!("{country_en},{country_ru},{region_en},{region_ru},{city_en},{city_ru},{lon},{lat},{population}\n", 
-			country_en = row.country_en.unwrap_or(STRING_EMPTY),
-			country_ru = row.country_ru.unwrap_or(STRING_EMPTY),
-			region_en  = row.region_en.unwrap_or(STRING_EMPTY),
-			region_ru  = row.region_ru.unwrap_or(STRING_EMPTY),
-			city_en    = row.city_en.unwrap_or(STRING_EMPTY),
-			city_ru    = row.city_ru.unwrap_or(STRING_EMPTY),
-			lon        = center.x(),
-			lat        = center.y(),
-			population = place.read().tags.get("population").cloned().unwrap_or(STRING_EMPTY)
-		);
-		file.write(s.as_bytes())?;
 	}
 
-	Ok(())
+	let mut row = export::HierarchyRow::default();
+	traverse(&mut row, place.clone());
+
+	let place = place.read();
+	let center = place.geometry.centroid().unwrap();
+	row.lon = center.x();
+	row.lat = center.y();
+	row.admin_level = place.tags.get("admin_level").and_then(|al| al.parse().ok());
+	row.tags = place.tags.clone();
+	row.parent_ids = place.parents.iter().map(|p| p.read().source.osm_id()).collect();
+	row
 }
 
-
 fn find_centers(pbf: &mut OsmPbfReader<File>, places: &mut Vec<Place>) {
 	println!("searching for places centers");
 
@@ -497,7 +670,7 @@ fn find_centers(pbf: &mut OsmPbfReader<File>, places: &mut Vec<Place>) {
 				.map(|n| (n, rf.role.clone()))
 			) {
 			if let Some(node) = nodes_ids_to_nodes.get(&center_node_id.0) {
-				place.tags.insert("center_role".into(), format!("role_{}", role).into());
+				place.center_role = format!("role_{}", role);
 				center = Some(geo::Coord {x: node.lon(), y: node.lat()});
 				found_centers_count.fetch_add(1, Ordering::Relaxed);
 			}
@@ -508,7 +681,7 @@ fn find_centers(pbf: &mut OsmPbfReader<File>, places: &mut Vec<Place>) {
 				if node.tags.get("name").is_some_and(|node_name| node_name == place.name()) {
 					let c = geo::Coord { x: node.lon(), y: node.lat() };
 					if place.geometry.contains(&c) {
-						place.tags.insert("center_role".into(), "name".into());
+						place.center_role = "name".into();
 						center = Some(c);
 						found_centers_count.fetch_add(1, Ordering::Relaxed);
 						break;
@@ -519,12 +692,12 @@ fn find_centers(pbf: &mut OsmPbfReader<File>, places: &mut Vec<Place>) {
 
 		if center.is_none() && let Some(centroid) = geo::Centroid::centroid(&place.geometry) {
 			center = Some(centroid.into());
-			place.tags.insert("center_role".into(), "centroid".into());
+			place.center_role = "centroid".into();
 		}
 
 		if center.is_none() {
 			let start = geo::CoordsIter::coords_iter(&place.geometry).next().unwrap();
-			place.tags.insert("center_role".into(), "start".into());
+			place.center_role = "start".into();
 			center = Some(start);
 		}
 	
@@ -535,6 +708,20 @@ fn find_centers(pbf: &mut OsmPbfReader<File>, places: &mut Vec<Place>) {
 	bar.finish_and_clear();
 }
 
+// place values of pipeline1 region label nodes and city nodes
+static REGION_PLACES: &[&str] = &["region", "state", "province"];
+static CITY_PLACES: &[&str] = &["city", "town"];
+
+/// Tag filters of pipeline1, recorded in `manifest.json`
+fn pipeline1_filters() -> serde_json::Value {
+	serde_json::json!({
+		"iso3166_1_keys": ISO3166_1_KEYS,
+		"region_places": REGION_PLACES,
+		"city_places": CITY_PLACES,
+		"member_roles": MEMBER_ROLES,
+	})
+}
+
 static PLACES: &[&str] = &["country", "state", "region", "city", "town", "village"];
 static ADMIN_LEVELS: &[&str] = &["2","3","4","8","9"];
 static MEMBER_ROLES: &[&str] = &["label", "admin_centre", "capital"];
@@ -546,37 +733,52 @@ fn has_admin_tags(tags: &Tags) -> bool {
 		|| tags.contains("boundary", "administrative") 
 }
 
-static TAGS_TO_RETAIN: &[&str] = &["name", "name_en", "admin_level", "place", "capital", "population", "boundary"];
+// https://wiki.openstreetmap.org/wiki/Key:ISO3166-1
+static ISO3166_1_KEYS: &[&str] = &["ISO3166-1:alpha2", "ISO3166-1"];
+
+fn iso3166_1(tags: &Tags) -> Option<&str> {
+	ISO3166_1_KEYS.iter().find_map(|key| tags.get(*key)).map(|code| code.as_str())
+}
+
+fn is_country(tags: &Tags) -> bool {
+	tags.contains("admin_level", "2") 
+		&& tags.contains("boundary", "administrative") 
+		&& iso3166_1(tags).is_some()
+}
+
+static TAGS_TO_RETAIN: &[&str] = &["name", "name_en", "admin_level", "place", "capital", "population", "boundary", "ISO3166-1", "ISO3166-1:alpha2"];
 fn filter_tags(tags: &mut Tags) -> &Tags {
 	tags.retain(|k, _| TAGS_TO_RETAIN.contains(&k.as_str()));
 	tags
 }
 
+/// Relations, then ways, then nodes are read only from blocks holding them (see `input::blobs::BlobIndex`), kept elements are written as nodes, ways
+/// and relations sorted by id. Elements are rewritten from osmpbfreader objects, so versions and authors are lost.
+/// Inputs with `LocationsOnWays` need no way nodes, `CACHE_LOCATIONS_ON_WAYS=1` writes the output that way too
 fn cache() -> anyhow::Result<()> { 
-	use osm_io::osm::{
-	   model::element::Element,
-	   pbf::{compression_type::CompressionType, file_info::FileInfo, parallel_writer::ParallelWriter},
-	   pbf::reader::Reader
-	};
+	use osm_io::osm::pbf::{compression_type::CompressionType, file_info::FileInfo, parallel_writer::ParallelWriter};
+	use input::Kind;
  
 	let osm_src = std::env::var("OSM_SRC")?;
-	let mut pbf = OsmPbfReader::new(File::open(Path::new(&osm_src)).unwrap());
-	
-	println!("counting elements");
-	let element_count = pbf.par_iter().count() as u64;
-	let bar = ProgressBar::new(element_count);
+	let source = input::Sources::open(&osm_src)?;
+	let passes = source.passes()?;
+	let locations_on_ways_input = passes.locations_on_ways();
+	let locations_on_ways_output = std::env::var("CACHE_LOCATIONS_ON_WAYS").is_ok_and(|v| v == "1");
+
+	let bar = ProgressBar::new(passes.blocks(Kind::Relation));
 	bar.enable_steady_tick(Duration::from_millis(200));
  
 	println!("parsing relations...");
-	pbf.rewind()?;
 	// taking all relevant relations
-	let relations = pbf.par_iter().filter_map(|obj| {
-		bar.inc(1);
-
-		let rel = obj.ok().and_then(|o| o.relation().cloned())?;
+	let mut relations = passes.filter_map(Kind::Relation, |obj, info| {
+		let rel = obj.relation().cloned()?;
 		
-		has_admin_tags(&rel.tags).then_some(rel)
-	}).collect::<Vec<_>>();
+		has_admin_tags(&rel.tags).then(|| (rel, info.meta()))
+	}, &bar)?;
+	// first input wins for elements shared by several of them
+	relations.sort_by_key(|(rel, _)| rel.id);
+	relations.dedup_by_key(|(rel, _)| rel.id);
+	let (relations, relation_metas): (Vec<_>, Vec<_>) = relations.into_iter().unzip();
  
 	println!("finished filtering {} relations!", relations.len());
 	
@@ -584,80 +786,235 @@ fn cache() -> anyhow::Result<()> {
 	let relations_nodes_ids = HashSet::<i64>::from_iter(relations.iter().map(
 	   |rel| rel.refs.iter().filter_map(|r| r.member.node().filter(|_| MEMBER_ROLES.contains(&r.role.as_str())).map(|id| id.0)).collect::<Vec<_>>()
 	).flatten());
-	let relations_ids = relations.into_iter().map(|rel| rel.id.0).collect::<HashSet<_>>();
  
  
 	println!("parsing ways...");
 	bar.reset();
-	pbf.rewind()?;
-	let ways = pbf.par_iter().filter_map(|obj| {
-		bar.inc(1);
-
-		let way = obj.ok().and_then(|o| o.way().cloned())?;
-
-		(relations_way_ids.contains(&way.id.0) || has_admin_tags(&way.tags)).then_some(way)
-	}).collect::<Vec<_>>();
+	bar.set_length(passes.blocks(Kind::Way));
+	let keep_way = |way: &Way| relations_way_ids.contains(&way.id.0) || has_admin_tags(&way.tags);
+	// locations are filled from nodes later when the input has none
+	let mut ways = match locations_on_ways_input {
+		true => passes.located_ways(|way, locations, info| keep_way(&way).then(|| ((way, locations), info.meta())), &bar)?,
+		false => passes.filter_map(Kind::Way, |obj, info| obj.way().filter(|way| keep_way(way)).map(|way| ((way.clone(), vec![]), info.meta())), &bar)?,
+	};
+	ways.sort_by_key(|((way, _), _)| way.id);
+	ways.dedup_by_key(|((way, _), _)| way.id);
+	let (mut ways, way_metas): (Vec<_>, Vec<_>) = ways.into_iter().unzip();
 	println!("finished parsing {} ways", ways.len());
-	let ways_nodes_ids = HashSet::<i64>::from_iter(ways.iter().map(|w| w.nodes.clone().iter().map(|id| id.0).collect::<Vec<i64>>()).flatten());
-	let ways_ids = ways.into_iter().map(|way| way.id.0).collect::<HashSet<_>>();
+	let ways_nodes_ids = match locations_on_ways_input {
+		true => HashSet::new(),
+		false => HashSet::<i64>::from_iter(ways.iter().flat_map(|(w, _)| w.nodes.iter().map(|id| id.0))),
+	};
  
  
 	println!("filtering nodes...");
 	bar.reset();
-	pbf.rewind()?;
-	let nodes_ids = pbf.par_iter().filter_map(|obj| {
-		bar.inc(1);
-		let node = obj.ok().and_then(|o| o.node().cloned())?;
+	bar.set_length(passes.blocks(Kind::Node));
+	let mut nodes = passes.filter_map(Kind::Node, |obj, info| {
+		let node = obj.node().cloned()?;
 		(relations_nodes_ids.contains(&node.id.0) 
 			|| ways_nodes_ids.contains(&node.id.0) 
 			|| has_admin_tags(&node.tags)
-		).then_some(node.id.0)
-	}).collect::<HashSet<_>>();
-	println!("finished parsing {} nodes", nodes_ids.len());
+		).then(|| (node, info.meta()))
+	}, &bar)?;
+	if locations_on_ways_input && !locations_on_ways_output {
+		// untagged way nodes go after the read ones, so sorting keeps the read copy of a way node with admin tags
+		nodes.extend(ways.iter().flat_map(|(way, locations)| way.nodes.iter().zip(locations).map(|(id, (decimicro_lat, decimicro_lon))| {
+			(Node { id: *id, tags: Tags::new(), decimicro_lat: *decimicro_lat, decimicro_lon: *decimicro_lon }, input::Meta::default())
+		})));
+	}
+	nodes.sort_by_key(|(node, _)| node.id);
+	nodes.dedup_by_key(|(node, _)| node.id);
+	let (mut nodes, node_metas): (Vec<_>, Vec<_>) = nodes.into_iter().unzip();
+	if !locations_on_ways_input && locations_on_ways_output {
+		let located = nodes.iter().map(|node| (node.id, (node.decimicro_lat, node.decimicro_lon))).collect::<HashMap<_, _>>();
+		for (way, locations) in ways.iter_mut() {
+			*locations = way.nodes.iter().filter_map(|id| located.get(id).copied()).collect();
+		}
+		// locations can't be written for nodes missing in the input, relations of such ways end up incomplete like with missing nodes
+		let count = ways.len();
+		ways.retain(|(way, locations)| locations.len() == way.nodes.len());
+		if ways.len() < count { println!("{} ways miss nodes, dropped", count - ways.len()); }
+		nodes.retain(|node| relations_nodes_ids.contains(&node.id.0) || has_admin_tags(&node.tags));
+	}
+	println!("finished parsing {} nodes", nodes.len());
+	bar.finish_and_clear();
  
-	let mut out_name = PathBuf::from_str(&osm_src)?; 
+	let mut out_name = source.0[0].path.clone(); 
 	out_name.set_file_name(
 		format!(
 			"{}_cache_{}.pbf", 
-			out_name.file_stem().unwrap().to_string_lossy(), 
+			match source.0.len() { 1 => out_name.file_stem().unwrap().to_string_lossy().to_string(), _ => "merged".to_string() }, 
 			Local::now().format("%d_%m_%H_%M")
 	));
+	// that form has no metadata, so metas needn't follow the retains above
+	if locations_on_ways_output {
+		locations_on_ways::write(&out_name, &nodes, &ways, &relations)?;
+		println!("cached version path: {out_name:#?}");
+		return Ok(());
+	}
+
 	// https://github.com/navigatorsguild/osm-io/blob/main/examples/parallel-pbf-io.rs#L22
 	
-	let tasks_count = std::thread::available_parallelism()?.into();
+	let tasks_count: usize = std::thread::available_parallelism()?.into();
 
 	let mut file_info = FileInfo::default(); file_info.with_writingprogram_str("planet-filtered");
 	let mut writer = ParallelWriter::from_file_info(tasks_count * 8000 * 32, 8000, out_name.clone(), file_info, CompressionType::Zlib).unwrap();
 	writer.write_header().unwrap();
  
-	println!("rewriting elements from source file");
-	let writer = Arc::new(Mutex::new(writer));
-	let shared_writer = writer.clone();
-	let shared_bar = bar.clone();
-
-	Reader::new(&PathBuf::from(&osm_src))?.parallel_for_each(tasks_count, move |element| {
-
-		let mut should_inc = true;
-		let preserve = match element {
-			Element::Relation { ref relation } 	=> relations_ids.contains(&relation.id()),
-			Element::Node { ref node } 			=> nodes_ids.contains(&node.id()),
-			Element::Way { ref way } 			=> ways_ids.contains(&way.id()),
-			_ => { 
-				should_inc = false;
-				false
-			},
-		};
-
-		if preserve { shared_writer.lock().write_element(element.clone())?; }
-		if should_inc { shared_bar.inc(1); }
-		Ok(())
-	})?;
-	
-	writer.lock().close()?;
-	bar.finish_and_clear();
+	println!("writing elements");
+	let elements = nodes.into_iter().map(OsmObj::Node).zip(node_metas)
+		.chain(ways.into_iter().map(|(way, _)| OsmObj::Way(way)).zip(way_metas))
+		.chain(relations.into_iter().map(OsmObj::Relation).zip(relation_metas));
+	for (element, meta) in elements {
+		writer.write_element(input::to_osm_io(element, meta))?;
+	}
+	writer.close()?;
 	
 	println!("cached version path: {out_name:#?}");
 
 	Ok(())
  }
- 
+
+fn osc_files(dir: &Path, files: &mut Vec<PathBuf>) -> anyhow::Result<()> {
+	for entry in std::fs::read_dir(dir)? {
+		let path = entry?.path();
+		if path.is_dir() {
+			osc_files(&path, files)?;
+		}
+		else if [".osc", ".osc.gz", ".osc.bz2"].iter().any(|ext| path.to_string_lossy().ends_with(ext)) {
+			files.push(path);
+		}
+	}
+	Ok(())
+}
+
+/// Applies osmChange files from `OSC_DIR` (recursively, in path order, so replication `000/123/456.osc.gz` works) to `OSM_SRC` made by `--cache`.
+/// Everything changed is kept only if it passes the same filters as `cache`, so elements that lost admin tags or are not referenced anymore are dropped.
+/// Members of new relations that were neither in the cache nor in the diffs can't be added, they are reported
+fn update_cache() -> anyhow::Result<()> {
+	use osm_io::osm::pbf::{compression_type::CompressionType, file_info::FileInfo, writer::Writer};
+	use input::Action;
+
+	let osm_src = std::env::var("OSM_SRC")?;
+	let mut files = Vec::new();
+	osc_files(Path::new(&std::env::var("OSC_DIR")?), &mut files)?;
+	files.sort();
+	if files.is_empty() {
+		anyhow::bail!("no .osc files in OSC_DIR");
+	}
+
+	let cache = input::Source::open(&osm_src)?;
+	if cache.locations_on_ways()? {
+		anyhow::bail!("{osm_src} has locations on ways, they would be lost, update a cache written without CACHE_LOCATIONS_ON_WAYS");
+	}
+
+	println!("loading cache");
+	let mut nodes = BTreeMap::new();
+	let mut ways = BTreeMap::new();
+	let mut relations = BTreeMap::new();
+	for obj in cache.elements()? {
+		match obj {
+			OsmObj::Node(node) => { nodes.insert(node.id.0, node); },
+			OsmObj::Way(way) => { ways.insert(way.id.0, way); },
+			OsmObj::Relation(relation) => { relations.insert(relation.id.0, relation); },
+		}
+	}
+	let cached_count = nodes.len() + ways.len() + relations.len();
+
+	let mut actions = HashMap::<&str, usize>::new();
+	for path in files.iter() {
+		println!("applying {}", path.display());
+		let changes = input::read_changes(input::Compression::of(path)?.reader(path)?)?;
+		for change in changes {
+			*actions.entry(match change.action {
+				Action::Create => "created",
+				Action::Modify => "modified",
+				Action::Delete => "deleted",
+			}).or_default() += 1;
+
+			match (change.action, change.obj) {
+				(Action::Delete, OsmObj::Node(node)) => { nodes.remove(&node.id.0); },
+				(Action::Delete, OsmObj::Way(way)) => { ways.remove(&way.id.0); },
+				(Action::Delete, OsmObj::Relation(relation)) => { relations.remove(&relation.id.0); },
+				(_, OsmObj::Node(node)) => { nodes.insert(node.id.0, node); },
+				(_, OsmObj::Way(way)) => { ways.insert(way.id.0, way); },
+				(_, OsmObj::Relation(relation)) => { relations.insert(relation.id.0, relation); },
+			}
+		}
+	}
+	println!("applied {} files: {actions:?}", files.len());
+
+	println!("filtering");
+	relations.retain(|_, rel| has_admin_tags(&rel.tags));
+	let relations_way_ids = relations.values().flat_map(|rel| rel.refs.iter().filter_map(|r| r.member.way().map(|id| id.0))).collect::<HashSet<_>>();
+	let relations_nodes_ids = relations.values()
+		.flat_map(|rel| rel.refs.iter().filter_map(|r| r.member.node().filter(|_| MEMBER_ROLES.contains(&r.role.as_str())).map(|id| id.0)))
+		.collect::<HashSet<_>>();
+
+	ways.retain(|id, way| relations_way_ids.contains(id) || has_admin_tags(&way.tags));
+	let ways_nodes_ids = ways.values().flat_map(|w| w.nodes.iter().map(|id| id.0)).collect::<HashSet<_>>();
+
+	nodes.retain(|id, node| relations_nodes_ids.contains(id) || ways_nodes_ids.contains(id) || has_admin_tags(&node.tags));
+
+	let missing_ways = relations_way_ids.iter().filter(|id| !ways.contains_key(id)).count();
+	let missing_nodes = relations_nodes_ids.iter().chain(ways_nodes_ids.iter()).collect::<HashSet<_>>().into_iter().filter(|id| !nodes.contains_key(id)).count();
+	if missing_ways + missing_nodes > 0 {
+		eprintln!("{missing_ways} member ways and {missing_nodes} nodes are neither in the cache nor in the diffs, rerun --cache on the planet to get them");
+	}
+
+	let mut out_name = PathBuf::from_str(&osm_src)?;
+	out_name.set_file_name(format!("{}_updated_{}.pbf", out_name.file_stem().unwrap().to_string_lossy(), Local::now().format("%d_%m_%H_%M")));
+
+	let mut file_info = FileInfo::default(); file_info.with_writingprogram_str("planet-filtered");
+	let mut writer = Writer::from_file_info(out_name.clone(), file_info, CompressionType::Zlib)?;
+	writer.write_header()?;
+	let written = nodes.len() + ways.len() + relations.len();
+	let elements = nodes.into_values().map(OsmObj::Node)
+		.chain(ways.into_values().map(OsmObj::Way))
+		.chain(relations.into_values().map(OsmObj::Relation));
+	for obj in elements {
+		writer.write_element(input::to_osm_io(obj, input::Meta::default()))?;
+	}
+	writer.close()?;
+
+	println!("{cached_count} elements in cache, {written} after update: {out_name:#?}");
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use osmpbfreader::{OsmId, Ref, RelationId};
+
+	use super::*;
+
+	fn node(id: i64, wikidata: Option<&str>) -> Node {
+		let mut tags = Tags::new();
+		if let Some(wikidata) = wikidata {
+			tags.insert("wikidata".into(), wikidata.into());
+		}
+		Node { id: NodeId(id), tags, decimicro_lat: 0, decimicro_lon: 0 }
+	}
+
+	fn relation(wikidata: &str, members: &[(i64, &str)]) -> Relation {
+		let mut tags = Tags::new();
+		tags.insert("wikidata".into(), wikidata.into());
+		let refs = members.iter().map(|(id, role)| Ref { member: OsmId::Node(NodeId(*id)), role: (*role).into() }).collect();
+		Relation { id: RelationId(1), tags, refs }
+	}
+
+	#[test]
+	fn label_role_ranks_before_wikidata() {
+		let nodes = [node(1, Some("Q1")), node(2, None), node(3, Some("Q2")), node(4, Some("Q1"))];
+		let candidates = nodes.iter().map(|n| (n.id, n)).collect::<HashMap<_, _>>();
+
+		let rel = relation("Q1", &[(1, "admin_centre"), (2, "label")]);
+		assert_eq!(find_label_node(&rel, &candidates), Some(NodeId(2)));
+		let rel = relation("Q1", &[(2, "label"), (4, "label")]);
+		assert_eq!(find_label_node(&rel, &candidates), Some(NodeId(4)));
+		let rel = relation("Q1", &[(3, ""), (1, "")]);
+		assert_eq!(find_label_node(&rel, &candidates), Some(NodeId(1)));
+		let rel = relation("Q9", &[(3, ""), (1, ""), (5, "label")]);
+		assert_eq!(find_label_node(&rel, &candidates), Some(NodeId(3)));
+	}
+}
@@ -0,0 +1,193 @@
+use std::{
+	collections::BTreeMap,
+	path::{Path, PathBuf},
+	time::Instant,
+};
+
+use chrono::{DateTime, Local};
+use serde::Serialize;
+
+use crate::{
+	input::{self, Compression, Format},
+	SyncPlace,
+};
+
+#[derive(Serialize)]
+pub struct PbfHeader {
+	pub writing_program: Option<String>,
+	pub source: Option<String>,
+	/// unix seconds of the last applied replication diff
+	pub replication_timestamp: Option<i64>,
+	pub replication_time: Option<String>,
+	pub replication_sequence_number: Option<i64>,
+	pub replication_base_url: Option<String>,
+	/// `[left, bottom, right, top]`
+	pub bbox: Option<[f64; 4]>,
+	pub required_features: Vec<String>,
+	pub optional_features: Vec<String>,
+}
+
+impl PbfHeader {
+	fn read(path: &Path) -> anyhow::Result<Self> {
+		let reader = osm_io::osm::pbf::reader::Reader::new(path)?;
+		let info = reader.info();
+		Ok(Self {
+			writing_program: info.writingprogram().clone(),
+			source: info.source().clone(),
+			replication_timestamp: *info.osmosis_replication_timestamp(),
+			replication_time: info.osmosis_replication_timestamp()
+				.and_then(|ts| DateTime::from_timestamp(ts, 0))
+				.map(|t| t.to_rfc3339()),
+			replication_sequence_number: *info.osmosis_replication_sequence_number(),
+			replication_base_url: info.osmosis_replication_base_url().clone(),
+			bbox: info.bounding_box().as_ref().map(|b| [b.left(), b.bottom(), b.right(), b.top()]),
+			required_features: info.required_features().clone(),
+			optional_features: info.optional_features().clone(),
+		})
+	}
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Source {
+	File {
+		path: PathBuf,
+		size: u64,
+		format: Format,
+		compression: Compression,
+		/// only PBF has it
+		header: Option<Box<PbfHeader>>,
+	},
+	Snapshot {
+		path: PathBuf,
+		rebuild_hierarchy: bool,
+	},
+	/// several input files merged into one
+	Merged {
+		files: Vec<Source>,
+	},
+	/// `extract_polygons` output
+	Extracted {
+		path: PathBuf,
+	},
+}
+
+impl Source {
+	pub fn file(path: &Path) -> anyhow::Result<Self> {
+		let source = input::Source::open(path)?;
+		Ok(Source::File {
+			path: path.to_path_buf(),
+			size: std::fs::metadata(path)?.len(),
+			format: source.format,
+			compression: source.compression,
+			header: (source.format == Format::Pbf).then(|| PbfHeader::read(path).map(Box::new)).transpose()?,
+		})
+	}
+
+	pub fn files(sources: &input::Sources) -> anyhow::Result<Self> {
+		match sources.0.as_slice() {
+			[source] => Self::file(&source.path),
+			sources => Ok(Source::Merged { files: sources.iter().map(|s| Self::file(&s.path)).collect::<anyhow::Result<_>>()? }),
+		}
+	}
+}
+
+#[derive(Serialize)]
+struct Timing {
+	stage: &'static str,
+	seconds: f64,
+}
+
+/// Counts by mapped type: `country`, `region`, `city`
+type Counts = BTreeMap<&'static str, usize>;
+
+#[derive(Serialize, Default)]
+struct Statistics {
+	/// places before hierarchy is built
+	assembled: Counts,
+	/// places written to outputs
+	written: Counts,
+	/// places dropped because no parent contains them
+	orphans: Counts,
+	/// `center_role` values by mapped type
+	centers: BTreeMap<&'static str, BTreeMap<String, usize>>,
+}
+
+/// `manifest.json` of a pipeline1 run: what it was made from, with which settings, how many places and how long it took
+#[derive(Serialize)]
+pub struct Manifest {
+	tool: &'static str,
+	version: &'static str,
+	created_at: String,
+	source: Option<Source>,
+	filters: serde_json::Value,
+	output_formats: Vec<String>,
+	csv_tables: Vec<String>,
+	statistics: Statistics,
+	timings: Vec<Timing>,
+	#[serde(skip)]
+	started: Instant,
+}
+
+fn count_by_type(places: &[SyncPlace]) -> Counts {
+	let mut counts = Counts::new();
+	for place in places {
+		*counts.entry(place.read().mapped_type.name()).or_default() += 1;
+	}
+	counts
+}
+
+impl Manifest {
+	pub fn new(filters: serde_json::Value, output_formats: Vec<String>, csv_tables: Vec<String>) -> Self {
+		Self {
+			tool: env!("CARGO_PKG_NAME"),
+			version: env!("CARGO_PKG_VERSION"),
+			created_at: Local::now().to_rfc3339(),
+			source: None,
+			filters,
+			output_formats,
+			csv_tables,
+			statistics: Statistics::default(),
+			timings: Vec::new(),
+			started: Instant::now(),
+		}
+	}
+
+	pub fn set_source(&mut self, source: Source) {
+		self.source = Some(source);
+	}
+
+	/// Runs `f` and records how long it took
+	pub fn stage<T>(&mut self, stage: &'static str, f: impl FnOnce() -> T) -> T {
+		let start = Instant::now();
+		let result = f();
+		self.timings.push(Timing { stage, seconds: start.elapsed().as_secs_f64() });
+		result
+	}
+
+	pub fn record_assembled(&mut self, places: &[SyncPlace]) {
+		self.statistics.assembled = count_by_type(places);
+	}
+
+	/// Written places, orphans are the rest of assembled ones
+	pub fn record_written(&mut self, places: &[SyncPlace]) {
+		let written = count_by_type(places);
+		let statistics = &mut self.statistics;
+		statistics.orphans = statistics.assembled.iter()
+			.map(|(mapped_type, assembled)| (*mapped_type, assembled.saturating_sub(written.get(mapped_type).copied().unwrap_or(0))))
+			.collect();
+		statistics.written = written;
+
+		statistics.centers.clear();
+		for place in places {
+			let place = place.read();
+			*statistics.centers.entry(place.mapped_type.name()).or_default().entry(place.center_role.clone()).or_default() += 1;
+		}
+	}
+
+	pub fn write(mut self, path: &Path) -> anyhow::Result<()> {
+		self.timings.push(Timing { stage: "total", seconds: self.started.elapsed().as_secs_f64() });
+		std::fs::write(path, serde_json::to_string_pretty(&self)?)?;
+		Ok(())
+	}
+}
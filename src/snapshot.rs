@@ -0,0 +1,114 @@
+use std::{
+	collections::HashMap,
+	fs::File,
+	io::{BufReader, BufWriter, Read, Write},
+	path::Path,
+	sync::Arc,
+};
+
+use osmpbfreader::OsmObj;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+
+use crate::{MappedType, Place, SyncPlace};
+
+const MAGIC: &[u8; 8] = b"RSOSMPLC";
+/// Bump on any change of `SnapshotPlace` or `Place` fields, old snapshots are refused
+const VERSION: u32 = 2;
+
+/// Place with parents as indices in the snapshot
+#[derive(Serialize, Deserialize)]
+struct SnapshotPlace {
+	mapped_type: MappedType,
+	parents: Vec<u32>,
+	geometry: geo::Geometry,
+	tags: HashMap<String, String>,
+	center: geo::Coord,
+	center_role: String,
+	source: OsmObj,
+}
+
+/// Writes `places` with their parent links as `MAGIC`, little endian `VERSION` and bincode body
+pub fn save(path: &Path, places: &[SyncPlace]) -> anyhow::Result<()> {
+	let indices = places.iter().enumerate()
+		.map(|(i, p)| (Arc::as_ptr(p), i as u32))
+		.collect::<HashMap<_, _>>();
+
+	let snapshot = places.iter().map(|place| {
+		let place = place.read();
+		SnapshotPlace {
+			mapped_type: place.mapped_type.clone(),
+			parents: place.parents.iter().filter_map(|p| indices.get(&Arc::as_ptr(p)).copied()).collect(),
+			geometry: place.geometry.clone(),
+			tags: place.tags.clone(),
+			center: place.center,
+			center_role: place.center_role.clone(),
+			source: place.source.clone(),
+		}
+	}).collect::<Vec<_>>();
+
+	let mut out = BufWriter::new(File::create(path)?);
+	out.write_all(MAGIC)?;
+	out.write_all(&VERSION.to_le_bytes())?;
+	bincode::serialize_into(&mut out, &snapshot)?;
+	out.flush()?;
+	Ok(())
+}
+
+pub fn load(path: &Path) -> anyhow::Result<Vec<SyncPlace>> {
+	let mut input = BufReader::new(File::open(path)?);
+	let mut header = [0u8; 12];
+	input.read_exact(&mut header)?;
+	if &header[..8] != MAGIC {
+		anyhow::bail!("{} is not a places snapshot", path.display());
+	}
+	let version = u32::from_le_bytes(header[8..].try_into()?);
+	if version != VERSION {
+		anyhow::bail!("snapshot {} has version {version}, this build reads {VERSION}, rerun pipeline1 on PBF", path.display());
+	}
+
+	let snapshot: Vec<SnapshotPlace> = bincode::deserialize_from(input)?;
+	let mut parents = Vec::with_capacity(snapshot.len());
+	let places = snapshot.into_iter().map(|place| {
+		parents.push(place.parents);
+		Arc::new(RwLock::new(Place {
+			mapped_type: place.mapped_type,
+			parents: vec![],
+			geometry: place.geometry,
+			tags: place.tags,
+			center: place.center,
+			center_role: place.center_role,
+			source: place.source,
+		}))
+	}).collect::<Vec<_>>();
+
+	for (place, parents) in places.iter().zip(parents) {
+		let mut place = place.write();
+		for parent in parents {
+			let parent = places.get(parent as usize).ok_or_else(|| anyhow::anyhow!("broken parent link in snapshot"))?;
+			place.parents.push(Arc::clone(parent));
+		}
+	}
+	Ok(places)
+}
+
+/// Nearest city under the smallest place containing the point, then places containing the point, smallest first
+pub fn lookup(places: &[SyncPlace], point: geo::Point) -> Vec<SyncPlace> {
+	use geo::{Area, Contains, EuclideanDistance};
+
+	let mut containing = places.iter()
+		.filter(|p| p.read().mapped_type != MappedType::City && p.read().geometry.contains(&point))
+		.cloned()
+		.collect::<Vec<_>>();
+	containing.sort_by(|a, b| a.read().geometry.unsigned_area().total_cmp(&b.read().geometry.unsigned_area()));
+
+	let city = containing.first().and_then(|smallest| places.iter()
+		.filter(|p| p.read().mapped_type == MappedType::City && p.read().parents.iter().any(|parent| Arc::ptr_eq(parent, smallest)))
+		.min_by(|a, b| {
+			let distance = |p: &SyncPlace| geo::Point(p.read().center).euclidean_distance(&point);
+			distance(a).total_cmp(&distance(b))
+		})
+		.cloned());
+
+	city.into_iter().chain(containing).collect()
+}